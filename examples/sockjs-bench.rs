@@ -0,0 +1,360 @@
+//! Load generator for sockjs server
+//!
+//! Opens concurrent client sessions, sends echo messages and topic
+//! publications, and reports latency percentiles, throughput, memory
+//! and dropped sessions.
+//!
+//! ```text
+//! cargo run --release --features client --example sockjs-bench -- \
+//!     --sessions 500 --transports websocket,xhr_streaming --rate 2 --broadcast 10
+//! ```
+//!
+//! Without `--url` bench starts local echo server with topics enabled.
+extern crate actix;
+extern crate actix_web;
+extern crate env_logger;
+extern crate futures;
+extern crate serde_json;
+extern crate sockjs;
+extern crate time;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::*;
+
+use sockjs::{ClientError, ClientEvent, ClientTransport, ClientWriter, Message, Session,
+             SockJSClient, SockJSContext, SockJSManager};
+
+const TOPIC: &str = "bench";
+
+const USAGE: &str = "\
+Usage: sockjs-bench [options]
+
+Options:
+    --url URL            sockjs application url, local server is started if not set
+    --sessions N         number of concurrent sessions [default: 100]
+    --transports LIST    comma separated websocket,xhr_streaming,xhr [default: websocket]
+    --rate N             echo messages per second per session [default: 1]
+    --broadcast N        topic publications per second [default: 0]
+    --size N             message payload size in bytes [default: 64]
+    --duration N         test duration in seconds [default: 10]
+";
+
+struct Config {
+    url: Option<String>,
+    sessions: usize,
+    transports: Vec<ClientTransport>,
+    rate: u64,
+    broadcast: u64,
+    size: usize,
+    duration: u64,
+}
+
+impl Config {
+    fn parse() -> Result<Config, String> {
+        let mut cfg = Config {
+            url: None,
+            sessions: 100,
+            transports: vec![ClientTransport::Websocket],
+            rate: 1,
+            broadcast: 0,
+            size: 64,
+            duration: 10,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(String::new())
+            }
+            let value = args.next().ok_or_else(|| format!("{} requires value", arg))?;
+            let number = || value.parse::<u64>().map_err(|_| format!("{} requires number", arg));
+            match arg.as_str() {
+                "--url" => cfg.url = Some(value.clone()),
+                "--sessions" => cfg.sessions = number()? as usize,
+                "--rate" => cfg.rate = number()?,
+                "--broadcast" => cfg.broadcast = number()?,
+                "--size" => cfg.size = number()? as usize,
+                "--duration" => cfg.duration = number()?,
+                "--transports" => {
+                    cfg.transports = value.split(',').map(|t| match t.trim() {
+                        "websocket" => Ok(ClientTransport::Websocket),
+                        "xhr_streaming" => Ok(ClientTransport::XhrStreaming),
+                        "xhr" => Ok(ClientTransport::XhrPolling),
+                        t => Err(format!("Unknown transport: {}", t)),
+                    }).collect::<Result<_, _>>()?;
+                }
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+        if cfg.transports.is_empty() {
+            return Err("At least one transport is required".to_owned())
+        }
+        Ok(cfg)
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    opened: usize,
+    dropped: usize,
+    errors: usize,
+    sent: usize,
+    published: usize,
+    received: usize,
+    bytes: usize,
+    /// Echo round trip, microseconds
+    echo: Vec<u64>,
+    /// Publication delivery, microseconds
+    broadcast: Vec<u64>,
+}
+
+/// Payload carries send time, so latency can be measured on receive
+fn payload(size: usize) -> String {
+    let mut s = format!("{}:", time::precise_time_ns());
+    while s.len() < size {
+        s.push('x');
+    }
+    s
+}
+
+fn latency(payload: &str) -> Option<u64> {
+    payload.split(':').next()
+        .and_then(|t| t.parse::<u64>().ok())
+        .map(|sent| time::precise_time_ns().saturating_sub(sent) / 1000)
+}
+
+/// Bench client session
+struct Peer {
+    client: Option<SockJSClient>,
+    writer: Option<ClientWriter>,
+    stats: Rc<RefCell<Stats>>,
+    rate: u64,
+    size: usize,
+    /// Publish to the topic, first peer is publisher
+    broadcast: u64,
+    closed: bool,
+}
+
+impl Actor for Peer {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        let (reader, writer) = self.client.take().unwrap().connect();
+        self.writer = Some(writer);
+        ctx.add_stream(reader);
+    }
+}
+
+impl Peer {
+    fn opened(&mut self, ctx: &mut Context<Self>) {
+        self.stats.borrow_mut().opened += 1;
+        self.send(format!(r#"{{"type":"subscribe","topic":"{}"}}"#, TOPIC));
+
+        if self.rate > 0 {
+            ctx.run_interval(Duration::from_secs(1) / self.rate as u32, |act, _| {
+                if !act.closed {
+                    act.stats.borrow_mut().sent += 1;
+                    let msg = payload(act.size);
+                    act.send(msg);
+                }
+            });
+        }
+        if self.broadcast > 0 {
+            ctx.run_interval(Duration::from_secs(1) / self.broadcast as u32, |act, _| {
+                if !act.closed {
+                    act.stats.borrow_mut().published += 1;
+                    let data = serde_json::to_string(&payload(act.size)).unwrap();
+                    act.send(format!(
+                        r#"{{"type":"publish","topic":"{}","data":{}}}"#, TOPIC, data));
+                }
+            });
+        }
+    }
+
+    fn send(&self, msg: String) {
+        if let Some(ref writer) = self.writer {
+            writer.send(msg);
+        }
+    }
+
+    fn dropped(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.stats.borrow_mut().dropped += 1;
+        }
+    }
+}
+
+impl StreamHandler<ClientEvent, ClientError> for Peer {
+
+    fn handle(&mut self, ev: ClientEvent, ctx: &mut Context<Self>) {
+        match ev {
+            ClientEvent::Open(_) => self.opened(ctx),
+            ClientEvent::Message(msg) => {
+                let mut stats = self.stats.borrow_mut();
+                stats.received += 1;
+                stats.bytes += msg.len();
+                if msg.starts_with('{') {
+                    let value: serde_json::Value = match serde_json::from_str(&msg) {
+                        Ok(value) => value,
+                        Err(_) => return,
+                    };
+                    if let Some(t) = value["data"].as_str().and_then(latency) {
+                        stats.broadcast.push(t);
+                    }
+                } else if let Some(t) = latency(&msg) {
+                    stats.echo.push(t);
+                }
+            }
+            ClientEvent::Reconnecting => self.stats.borrow_mut().errors += 1,
+            ClientEvent::Close(_) => self.dropped(),
+        }
+    }
+
+    fn error(&mut self, err: ClientError, _: &mut Context<Self>) -> Running {
+        println!("Session error: {}", err);
+        self.stats.borrow_mut().errors += 1;
+        self.dropped();
+        Running::Continue
+    }
+
+    fn finished(&mut self, _: &mut Context<Self>) {
+        self.dropped();
+    }
+}
+
+#[derive(Default)]
+struct Echo;
+
+impl Actor for Echo {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Echo {}
+
+impl Handler<Message> for Echo {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.send(msg);
+    }
+}
+
+/// Start local echo server in its own thread and system,
+/// so it does not compete with clients for the event loop.
+/// Returns application url
+fn start_server() -> String {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = actix::System::new("sockjs-bench-server");
+        let sm: Addr<Syn, _> = SockJSManager::<Echo>::default().topics(true).start();
+
+        let srv = server::new(move || App::new().handler("/echo", sockjs::SockJS::new(sm.clone())))
+            .bind("127.0.0.1:0").unwrap();
+        let _ = tx.send(srv.addrs()[0]);
+        srv.start();
+        let _ = sys.run();
+    });
+    format!("http://{}/echo", rx.recv().unwrap())
+}
+
+/// Prints report and stops system after test duration
+struct Reporter {
+    cfg: Config,
+    stats: Rc<RefCell<Stats>>,
+    start: u64,
+}
+
+impl Actor for Reporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_later(Duration::from_secs(self.cfg.duration), |act, _| {
+            let elapsed = (time::precise_time_ns() - act.start) as f64 / 1e9;
+            report(&act.cfg, &mut act.stats.borrow_mut(), elapsed);
+            Arbiter::system().do_send(actix::msgs::SystemExit(0));
+        });
+    }
+}
+
+/// Resident set size of the process, linux only
+fn memory() -> Option<String> {
+    let mut status = String::new();
+    File::open("/proc/self/status").ok()?.read_to_string(&mut status).ok()?;
+    status.lines()
+        .find(|l| l.starts_with("VmRSS:"))
+        .map(|l| l["VmRSS:".len()..].trim().to_owned())
+}
+
+fn percentiles(name: &str, samples: &mut Vec<u64>) {
+    if samples.is_empty() {
+        return println!("{:>10}: no samples", name)
+    }
+    samples.sort();
+    let p = |q: f64| samples[((samples.len() - 1) as f64 * q) as usize] as f64 / 1000.0;
+    println!("{:>10}: p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  max {:.2}ms  ({} samples)",
+             name, p(0.5), p(0.9), p(0.99), p(1.0), samples.len());
+}
+
+fn report(cfg: &Config, stats: &mut Stats, elapsed: f64) {
+    println!("sessions: {} requested, {} opened, {} dropped, {} errors",
+             cfg.sessions, stats.opened, stats.dropped, stats.errors);
+    println!("messages: {} sent, {} published, {} received",
+             stats.sent, stats.published, stats.received);
+    println!("throughput: {:.0} msg/s, {:.0} KiB/s",
+             stats.received as f64 / elapsed, stats.bytes as f64 / 1024.0 / elapsed);
+    percentiles("echo", &mut stats.echo);
+    percentiles("broadcast", &mut stats.broadcast);
+    if let Some(mem) = memory() {
+        println!("memory: {} resident{}", mem,
+                 if cfg.url.is_none() { " (including local server)" } else { "" });
+    }
+}
+
+fn main() {
+    let cfg = match Config::parse() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("{}\n", err);
+            }
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "warn");
+    }
+    env_logger::init();
+
+    let url = cfg.url.clone().unwrap_or_else(start_server);
+    let sys = actix::System::new("sockjs-bench");
+    println!("Running {} sessions against {} for {}s", cfg.sessions, url, cfg.duration);
+
+    let stats = Rc::new(RefCell::new(Stats::default()));
+    for i in 0..cfg.sessions {
+        let transport = cfg.transports[i % cfg.transports.len()];
+        let stats = Rc::clone(&stats);
+        let client = SockJSClient::new(url.as_str()).transports(vec![transport]);
+        let (rate, size) = (cfg.rate, cfg.size);
+        let broadcast = if i == 0 { cfg.broadcast } else { 0 };
+        let _: Addr<Unsync, _> = Peer::create(move |_| Peer {
+            client: Some(client), writer: None,
+            stats, rate, size, broadcast,
+            closed: false,
+        });
+    }
+
+    let _: Addr<Unsync, _> = Reporter::create(move |_| Reporter{cfg, stats, start: time::precise_time_ns()});
+
+    let _ = sys.run();
+}
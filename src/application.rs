@@ -5,16 +5,19 @@ use std::rc::Rc;
 
 use actix::{Actor, Addr, Syn};
 use actix_web::dev::{AsyncResult, Handler, Resource};
-use actix_web::{HttpMessage, HttpRequest, HttpResponse};
-use futures::future::Either;
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::{self, Either};
+use futures::Future;
 use http::{header, Method};
 use md5;
 use rand::{self, Rng, ThreadRng};
 
 use context::SockJSContext;
-use manager::SessionManager;
+use manager::{GetMetrics, SessionManager, SockJSManager};
+use metrics;
 use protocol;
 use session::Session;
+use sharded::{shard_index, ShardedManager};
 use transports;
 use utils::{Info, SockjsHeaders};
 
@@ -26,7 +29,8 @@ where
     A: Actor<Context = SockJSContext<A>> + Session,
     SM: SessionManager<A>,
 {
-    manager: Rc<Addr<Syn, SM>>,
+    /// Session managers, session is handled by manager selected by session id hash
+    managers: Vec<Rc<Addr<Syn, SM>>>,
     act: PhantomData<A>,
     state: PhantomData<S>,
     rng: RefCell<ThreadRng>,
@@ -36,22 +40,28 @@ where
     disabled_transports: HashSet<String>,
     max_size: usize,
     cookie_needed: bool,
+    metrics: bool,
+    deflate: Option<transports::DeflateConfig>,
+    compression: bool,
+    uncompressed: HashSet<String>,
 }
 
-const ROUTES: [RouteType; 5] = [
+const ROUTES: [RouteType; 6] = [
     RouteType::Info,
     RouteType::Transport,
     RouteType::RawWebsocket,
     RouteType::IFrame,
     RouteType::IFrame,
+    RouteType::Metrics,
 ];
 
-const PATTERNS: [&str; 5] = [
+const PATTERNS: [&str; 6] = [
     "info",
     "{server}/{session}/{transport}",
     "websocket",
     "iframe.html",
     "iframe{version}.html",
+    "metrics",
 ];
 
 impl<A, SM, S> SockJS<A, SM, S>
@@ -75,12 +85,16 @@ where
             act: PhantomData,
             state: PhantomData,
             rng: RefCell::new(rand::thread_rng()),
-            manager: Rc::new(manager),
+            managers: vec![Rc::new(manager)],
             iframe_html: Rc::new(html),
             iframe_html_md5: format!("{:x}", digest),
             disabled_transports: HashSet::new(),
             max_size: transports::MAXSIZE,
             cookie_needed: false,
+            metrics: false,
+            deflate: None,
+            compression: false,
+            uncompressed: HashSet::new(),
         }
     }
 
@@ -107,6 +121,76 @@ where
         self.cookie_needed = val;
         self
     }
+
+    /// Negotiate `permessage-deflate` extension on websocket transports.
+    ///
+    /// Outbound messages that are not smaller than threshold get compressed,
+    /// compressed inbound messages get decompressed before they are
+    /// delivered to the session.
+    #[cfg(feature = "permessage-deflate")]
+    pub fn permessage_deflate(mut self, cfg: transports::DeflateConfig) -> Self {
+        self.deflate = Some(cfg);
+        self
+    }
+
+    /// Compress streaming transports (XhrStreaming, EventSource, HTMLFile)
+    /// with gzip or deflate if client accepts compressed response.
+    ///
+    /// Every frame is flushed, max size of streaming request is counted
+    /// before compression. By default compression is disabled.
+    #[cfg(feature = "compress")]
+    pub fn compression(mut self, enable: bool) -> Self {
+        self.compression = enable;
+        self
+    }
+
+    /// Do not compress specific streaming transports
+    #[cfg(feature = "compress")]
+    pub fn disable_compression<T, I>(mut self, transports: I) -> Self
+    where
+        T: Into<String>,
+        I: IntoIterator<Item = T>,
+    {
+        for i in transports {
+            self.uncompressed.insert(i.into());
+        }
+        self
+    }
+
+    /// Expose collected metrics at `metrics` path in prometheus text format.
+    ///
+    /// By default metrics endpoint is disabled.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self, enable: bool) -> Self {
+        self.metrics = enable;
+        self
+    }
+}
+
+impl<A, S> SockJS<A, SockJSManager<A>, S>
+where
+    A: Actor<Context = SockJSContext<A>> + Session,
+    S: 'static,
+{
+    /// Create new sockjs application for sharded session manager.
+    ///
+    /// Transport requests are sent straight to the shard that owns the session.
+    pub fn sharded(manager: &ShardedManager<A>) -> Self {
+        let mut app = SockJS::new(manager.all()[0].clone());
+        app.managers = manager.all().iter().map(|addr| Rc::new(addr.clone())).collect();
+        app
+    }
+}
+
+impl<A, SM, S> SockJS<A, SM, S>
+where
+    A: Actor<Context = SockJSContext<A>> + Session,
+    SM: SessionManager<A>,
+{
+    /// Manager that handles session
+    fn manager(&self, sid: &str) -> Rc<Addr<Syn, SM>> {
+        Rc::clone(&self.managers[shard_index(sid, self.managers.len())])
+    }
 }
 
 #[derive(Debug)]
@@ -115,6 +199,7 @@ enum RouteType {
     Transport,
     IFrame,
     RawWebsocket,
+    Metrics,
 }
 
 impl<A, SM, S> Handler<S> for SockJS<A, SM, S>
@@ -193,8 +278,28 @@ where
                         .into()
                 }
             }
+            RouteType::Metrics => {
+                if !self.metrics {
+                    HttpResponse::NotFound().finish().into()
+                } else if *req.method() == Method::GET {
+                    // only managers of this application are rendered
+                    let regs = self.managers.iter().map(|sm| sm.send(GetMetrics));
+                    let fut = future::join_all(regs.collect::<Vec<_>>())
+                        .from_err()
+                        .map(|regs| {
+                            let regs: Vec<_> = regs.into_iter().filter_map(|r| r.ok()).collect();
+                            HttpResponse::Ok()
+                                .content_type("text/plain; version=0.0.4")
+                                .sockjs_no_cache()
+                                .body(metrics::render(&regs))
+                        });
+                    let fut: Box<Future<Item = HttpResponse, Error = Error>> = Box::new(fut);
+                    fut.into()
+                } else {
+                    HttpResponse::MethodNotAllowed().finish().into()
+                }
+            }
             RouteType::Transport => {
-                let req2 = req.change_state(Rc::clone(&self.manager));
                 let tr = req.match_info().get("transport").unwrap().to_owned();
                 if self.disabled_transports.contains(&tr) {
                     return HttpResponse::NotFound().finish().into();
@@ -214,11 +319,16 @@ where
                         server
                     );
                 }
+                let req2 = {
+                    let manager = self.manager(req.match_info().get("session").unwrap());
+                    req.change_state(manager)
+                };
 
+                let compress = self.compression && !self.uncompressed.contains(&tr);
                 if tr == "websocket" {
-                    transports::Websocket::<A, _>::init(req2).into()
+                    transports::Websocket::<A, _>::init(req2, self.deflate.as_ref()).into()
                 } else if tr == "xhr_streaming" {
-                    transports::XhrStreaming::<A, _>::init(req2, self.max_size).into()
+                    transports::XhrStreaming::<A, _>::init(req2, self.max_size, compress).into()
                 } else if tr == "xhr" {
                     transports::Xhr::<A, _>::init(req2).into()
                 } else if tr == "xhr_send" {
@@ -227,9 +337,9 @@ where
                         Either::B(fut) => fut.into(),
                     }
                 } else if tr == "htmlfile" {
-                    transports::HTMLFile::<A, _>::init(req2, self.max_size).into()
+                    transports::HTMLFile::<A, _>::init(req2, self.max_size, compress).into()
                 } else if tr == "eventsource" {
-                    transports::EventSource::<A, _>::init(req2, self.max_size).into()
+                    transports::EventSource::<A, _>::init(req2, self.max_size, compress).into()
                 } else if tr == "jsonp" {
                     transports::JSONPolling::<A, _>::init(req2).into()
                 } else if tr == "jsonp_send" {
@@ -242,7 +352,9 @@ where
                 }
             }
             RouteType::RawWebsocket => {
-                transports::RawWebsocket::init(req.change_state(Rc::clone(&self.manager))).into()
+                let sid = format!("{}", rand::random::<u32>());
+                let req2 = req.change_state(self.manager(&sid));
+                transports::RawWebsocket::init(req2, sid, self.deflate.as_ref()).into()
             }
         }
     }
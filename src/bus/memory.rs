@@ -0,0 +1,34 @@
+use std::sync::{Arc, Mutex};
+
+use actix::{Recipient, SendError, Syn};
+
+use super::{BroadcastBus, BusMessage};
+
+/// In-process broadcast bus
+///
+/// Clones of the bus share subscribers, so several managers
+/// in one process can be connected with clones of the same bus.
+#[derive(Clone, Default)]
+pub struct MemoryBus {
+    subscribers: Arc<Mutex<Vec<Recipient<Syn, BusMessage>>>>,
+}
+
+impl MemoryBus {
+    pub fn new() -> MemoryBus {
+        MemoryBus::default()
+    }
+}
+
+impl BroadcastBus for MemoryBus {
+    fn publish(&self, msg: BusMessage) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| match s.do_send(msg.clone()) {
+            Err(SendError::Closed(_)) => false,
+            _ => true,
+        });
+    }
+
+    fn subscribe(&self, subscriber: Recipient<Syn, BusMessage>) {
+        self.subscribers.lock().unwrap().push(subscriber);
+    }
+}
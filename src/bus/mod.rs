@@ -0,0 +1,59 @@
+//! Broadcast bus connects session managers, so broadcasts
+//! and messages to specific sessions reach sessions on every node.
+use actix::{Recipient, Syn};
+use serde_json::Value;
+
+mod memory;
+mod net;
+
+pub use self::memory::MemoryBus;
+pub use self::net::NetBus;
+
+/// Message published to the broadcast bus
+#[derive(Clone, Debug, Message, Serialize, Deserialize)]
+pub enum BusMessage {
+    /// Message for all sessions
+    Broadcast {
+        /// Id of manager that published message
+        origin: u64,
+        msg: String,
+    },
+    /// Message for specific session
+    Send {
+        /// Id of manager that published message
+        origin: u64,
+        sid: String,
+        msg: String,
+    },
+    /// Publication to the topic
+    Publish {
+        /// Id of manager that published message
+        origin: u64,
+        topic: String,
+        data: Value,
+    },
+}
+
+impl BusMessage {
+    /// Id of manager that published message
+    pub fn origin(&self) -> u64 {
+        match *self {
+            BusMessage::Broadcast { origin, .. }
+            | BusMessage::Send { origin, .. }
+            | BusMessage::Publish { origin, .. } => origin,
+        }
+    }
+}
+
+/// Broadcast bus
+///
+/// Session manager publishes broadcasts and messages for sessions
+/// it does not own to the bus, and delivers messages received
+/// from the bus to its own sessions.
+pub trait BroadcastBus: Send + 'static {
+    /// Publish message to all subscribers
+    fn publish(&self, msg: BusMessage);
+
+    /// Subscribe to messages published to the bus
+    fn subscribe(&self, subscriber: Recipient<Syn, BusMessage>);
+}
@@ -0,0 +1,169 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+use actix::{Recipient, Syn};
+use serde_json;
+
+use super::{BroadcastBus, BusMessage, MemoryBus};
+
+/// Peer address
+enum PeerAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl PeerAddr {
+    fn connect(&self) -> io::Result<Box<Write + Send>> {
+        match *self {
+            PeerAddr::Tcp(ref addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            PeerAddr::Unix(ref path) => Ok(Box::new(UnixStream::connect(path)?)),
+        }
+    }
+}
+
+/// Write messages to the peer, connection is established lazily
+/// and re-established after errors
+fn spawn_writer(addr: PeerAddr) -> io::Result<Sender<Arc<Vec<u8>>>> {
+    let (tx, rx) = channel::<Arc<Vec<u8>>>();
+    thread::Builder::new()
+        .name("sockjs-bus-peer".to_owned())
+        .spawn(move || {
+            let mut conn = None;
+            for line in rx {
+                if conn.is_none() {
+                    match addr.connect() {
+                        Ok(c) => conn = Some(c),
+                        Err(err) => {
+                            warn!("Broadcast bus: can not connect to peer: {}", err);
+                            continue
+                        }
+                    }
+                }
+                let failed = if let Some(ref mut conn) = conn {
+                    conn.write_all(&line).and_then(|_| conn.flush()).is_err()
+                } else {
+                    false
+                };
+                if failed {
+                    conn.take();
+                }
+            }
+        })?;
+    Ok(tx)
+}
+
+/// Broadcast bus over tcp or unix sockets
+///
+/// Every node listens on its own address and connects to the addresses
+/// of other nodes. Messages are encoded as json, one message per line.
+/// Connections to peers are established lazily and re-established
+/// after errors, messages published while peer is unreachable are dropped.
+/// Every peer is served by its own writer thread, so publishing never
+/// blocks on peer i/o.
+#[derive(Clone)]
+pub struct NetBus {
+    peers: Vec<Sender<Arc<Vec<u8>>>>,
+    local: MemoryBus,
+}
+
+impl NetBus {
+    /// Listen on tcp address and publish messages to tcp peers
+    pub fn tcp<A: ToSocketAddrs>(addr: A, peers: &[SocketAddr]) -> io::Result<NetBus> {
+        let listener = TcpListener::bind(addr)?;
+        let bus = NetBus::with_peers(peers.iter().map(|addr| PeerAddr::Tcp(*addr)).collect())?;
+
+        let local = bus.local.clone();
+        thread::Builder::new()
+            .name("sockjs-bus".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => spawn_reader(stream, local.clone()),
+                        Err(err) => error!("Broadcast bus accept error: {}", err),
+                    }
+                }
+            })?;
+        Ok(bus)
+    }
+
+    /// Listen on unix socket and publish messages to unix socket peers
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<Path>>(path: P, peers: &[PathBuf]) -> io::Result<NetBus> {
+        let listener = UnixListener::bind(path)?;
+        let bus = NetBus::with_peers(
+            peers.iter().map(|path| PeerAddr::Unix(path.clone())).collect())?;
+
+        let local = bus.local.clone();
+        thread::Builder::new()
+            .name("sockjs-bus".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => spawn_reader(stream, local.clone()),
+                        Err(err) => error!("Broadcast bus accept error: {}", err),
+                    }
+                }
+            })?;
+        Ok(bus)
+    }
+
+    fn with_peers(peers: Vec<PeerAddr>) -> io::Result<NetBus> {
+        Ok(NetBus {
+            peers: peers.into_iter().map(spawn_writer).collect::<io::Result<_>>()?,
+            local: MemoryBus::new(),
+        })
+    }
+}
+
+/// Read messages from peer connection and publish them to local subscribers
+fn spawn_reader<R: Read + Send + 'static>(stream: R, local: MemoryBus) {
+    let res = thread::Builder::new()
+        .name("sockjs-bus-conn".to_owned())
+        .spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                match line {
+                    Ok(line) => match serde_json::from_str(&line) {
+                        Ok(msg) => local.publish(msg),
+                        Err(err) => warn!("Broadcast bus: can not decode message: {}", err),
+                    },
+                    Err(_) => break,
+                }
+            }
+        });
+    if let Err(err) = res {
+        error!("Broadcast bus: can not start reader: {}", err);
+    }
+}
+
+impl BroadcastBus for NetBus {
+    fn publish(&self, msg: BusMessage) {
+        let mut line = serde_json::to_vec(&msg).unwrap();
+        line.push(b'\n');
+
+        // local subscribers
+        self.local.publish(msg);
+
+        // remote peers
+        let line = Arc::new(line);
+        for peer in &self.peers {
+            let _ = peer.send(Arc::clone(&line));
+        }
+    }
+
+    fn subscribe(&self, subscriber: Recipient<Syn, BusMessage>) {
+        self.local.subscribe(subscriber)
+    }
+}
@@ -0,0 +1,358 @@
+use std::mem;
+use std::str;
+
+use actix::*;
+use actix_web::{client, ws};
+use actix_web::error::PayloadError;
+use bytes::{Bytes, BytesMut};
+use futures::Future;
+use futures::sync::mpsc::UnboundedSender;
+use rand::{self, Rng};
+use serde_json;
+
+use protocol::Frame;
+
+use super::{ClientError, ClientEvent, ClientTransport, SockJSClient};
+
+#[derive(Message)]
+pub struct Outgoing(pub String);
+
+#[derive(Message)]
+pub struct Shutdown;
+
+#[derive(Deserialize)]
+struct Info {
+    websocket: bool,
+}
+
+/// Client session, drives transport requests
+pub struct Connection {
+    cfg: SockJSClient,
+    events: UnboundedSender<Result<ClientEvent, ClientError>>,
+    /// Session url, `<url>/<server>/<session>`
+    url: String,
+    /// Transports left to try, next one is last
+    candidates: Vec<ClientTransport>,
+    transport: Option<ClientTransport>,
+    ws: Option<ws::ClientWriter>,
+    /// Incomplete line of http response
+    buf: BytesMut,
+    /// Messages waiting for session to open or for previous `xhr_send`
+    outgoing: Vec<String>,
+    sending: bool,
+    reconnecting: bool,
+    opened: bool,
+    closed: bool,
+    attempts: usize,
+}
+
+impl Actor for Connection {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.handshake(ctx);
+    }
+}
+
+impl Connection {
+    pub fn new(cfg: SockJSClient, events: UnboundedSender<Result<ClientEvent, ClientError>>)
+               -> Connection
+    {
+        Connection {
+            cfg, events,
+            url: String::new(),
+            candidates: Vec::new(),
+            transport: None,
+            ws: None,
+            buf: BytesMut::new(),
+            outgoing: Vec::new(),
+            sending: false,
+            reconnecting: false,
+            opened: false,
+            closed: false,
+            attempts: 0,
+        }
+    }
+
+    fn emit(&self, ev: ClientEvent) {
+        let _ = self.events.unbounded_send(Ok(ev));
+    }
+
+    /// Report error and stop client
+    fn failed(&mut self, err: ClientError, ctx: &mut Context<Self>) {
+        let _ = self.events.unbounded_send(Err(err));
+        self.closed = true;
+        ctx.stop();
+    }
+
+    /// Connection dropped without close frame, open new session
+    /// if reconnect attempts are left
+    fn lost(&mut self, err: ClientError, ctx: &mut Context<Self>) {
+        if self.closed || self.reconnecting {
+            return
+        }
+        self.transport = None;
+        self.ws = None;
+        self.opened = false;
+        self.sending = false;
+        self.buf.clear();
+
+        if self.attempts < self.cfg.attempts {
+            self.attempts += 1;
+            self.reconnecting = true;
+            self.emit(ClientEvent::Reconnecting);
+            ctx.run_later(self.cfg.delay, |act, ctx| act.handshake(ctx));
+        } else {
+            self.failed(err, ctx);
+        }
+    }
+
+    /// Request `/info` and open new session
+    fn handshake(&mut self, ctx: &mut Context<Self>) {
+        self.reconnecting = false;
+        let req = match client::get(format!("{}/info", self.cfg.url)).finish() {
+            Ok(req) => req,
+            Err(err) => return self.failed(ClientError::Info(err.to_string()), ctx),
+        };
+        let fut = req.send()
+            .map_err(|e| e.to_string())
+            .and_then(|resp| resp.body().map_err(|e| e.to_string()))
+            .and_then(|body| serde_json::from_slice::<Info>(&body).map_err(|e| e.to_string()))
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(info) => {
+                        act.candidates = act.cfg.transports.iter().rev()
+                            .filter(|t| info.websocket || **t != ClientTransport::Websocket)
+                            .cloned()
+                            .collect();
+                        let mut rng = rand::thread_rng();
+                        let server = rng.gen_range(0, 1000);
+                        let session: String = rng.gen_ascii_chars().take(8).collect();
+                        act.url = format!("{}/{:03}/{}", act.cfg.url, server, session);
+                        act.open(ctx);
+                    }
+                    Err(err) => act.lost(ClientError::Info(err), ctx),
+                }
+                fut::ok(())
+            });
+        ctx.spawn(fut);
+    }
+
+    /// Try next transport
+    fn open(&mut self, ctx: &mut Context<Self>) {
+        let transport = match self.candidates.pop() {
+            Some(transport) => transport,
+            None => return self.lost(ClientError::NoTransport, ctx),
+        };
+        debug!("Opening sockjs session {} with {} transport", self.url, transport.as_str());
+
+        match transport {
+            ClientTransport::Websocket => {
+                let url = format!("{}/websocket", self.url.replacen("http", "ws", 1));
+                let fut = ws::Client::new(url).connect()
+                    .into_actor(self)
+                    .then(|res, act, ctx| {
+                        match res {
+                            Ok((reader, writer)) => {
+                                act.transport = Some(ClientTransport::Websocket);
+                                act.ws = Some(writer);
+                                ctx.add_stream(reader);
+                            }
+                            Err(err) => {
+                                debug!("Websocket transport is not available: {}", err);
+                                act.open(ctx);
+                            }
+                        }
+                        fut::ok(())
+                    });
+                ctx.spawn(fut);
+            }
+            _ => self.poll(transport, ctx),
+        }
+    }
+
+    /// Start http receiving request
+    fn poll(&mut self, transport: ClientTransport, ctx: &mut Context<Self>) {
+        let req = match client::post(format!("{}/{}", self.url, transport.as_str())).finish() {
+            Ok(req) => req,
+            Err(err) => return self.failed(ClientError::Protocol(err.to_string()), ctx),
+        };
+        let fut = req.send()
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                match res {
+                    Ok(resp) => if resp.status().is_success() {
+                        act.transport = Some(transport);
+                        ctx.add_stream(resp);
+                    } else {
+                        debug!("{} transport failed: {}", transport.as_str(), resp.status());
+                        act.next(ctx);
+                    },
+                    Err(err) => {
+                        debug!("{} transport failed: {}", transport.as_str(), err);
+                        act.next(ctx);
+                    }
+                }
+                fut::ok(())
+            });
+        ctx.spawn(fut);
+    }
+
+    /// Current transport failed, fall back to next transport
+    /// if session is not opened yet
+    fn next(&mut self, ctx: &mut Context<Self>) {
+        if self.opened {
+            self.lost(ClientError::Disconnected, ctx)
+        } else {
+            self.open(ctx)
+        }
+    }
+
+    /// Handle frame sent by server
+    fn received(&mut self, s: &str, ctx: &mut Context<Self>) {
+        let frames = match Frame::decode(s) {
+            Ok(frames) => frames,
+            Err(err) => return self.failed(ClientError::Protocol(err), ctx),
+        };
+        for frame in frames {
+            match frame {
+                Frame::Open => {
+                    self.opened = true;
+                    self.attempts = 0;
+                    if let Some(transport) = self.transport {
+                        self.emit(ClientEvent::Open(transport));
+                    }
+                    self.flush(ctx);
+                }
+                Frame::Message(msg) => self.emit(ClientEvent::Message(msg)),
+                Frame::Close(code) => {
+                    self.closed = true;
+                    self.emit(ClientEvent::Close(code));
+                    return ctx.stop()
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Send queued messages
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        if !self.opened || self.sending || self.outgoing.is_empty() {
+            return
+        }
+        if let Some(ref mut writer) = self.ws {
+            // server expects single message per websocket frame
+            for msg in self.outgoing.drain(..) {
+                writer.text(serde_json::to_string(&[msg]).unwrap());
+            }
+            return
+        }
+
+        let body = serde_json::to_string(&mem::replace(&mut self.outgoing, Vec::new())).unwrap();
+        let req = match client::post(format!("{}/xhr_send", self.url)).body(body) {
+            Ok(req) => req,
+            Err(err) => return self.failed(ClientError::Send(err.to_string()), ctx),
+        };
+        self.sending = true;
+        let fut = req.send()
+            .map_err(|e| e.to_string())
+            .and_then(|resp| if resp.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("{}", resp.status()))
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                act.sending = false;
+                if let Err(err) = res {
+                    let _ = act.events.unbounded_send(Err(ClientError::Send(err)));
+                }
+                act.flush(ctx);
+                fut::ok(())
+            });
+        ctx.spawn(fut);
+    }
+}
+
+impl Handler<Outgoing> for Connection {
+    type Result = ();
+
+    fn handle(&mut self, msg: Outgoing, ctx: &mut Context<Self>) {
+        self.outgoing.push(msg.0);
+        self.flush(ctx);
+    }
+}
+
+impl Handler<Shutdown> for Connection {
+    type Result = ();
+
+    fn handle(&mut self, _: Shutdown, ctx: &mut Context<Self>) {
+        if let Some(mut writer) = self.ws.take() {
+            writer.close(None);
+        }
+        self.closed = true;
+        ctx.stop();
+    }
+}
+
+/// Http transports response body
+impl StreamHandler<Bytes, PayloadError> for Connection {
+
+    fn handle(&mut self, chunk: Bytes, ctx: &mut Context<Self>) {
+        self.buf.extend_from_slice(&chunk);
+        while let Some(idx) = self.buf.iter().position(|b| *b == b'\n') {
+            let line = self.buf.split_to(idx + 1);
+            match str::from_utf8(&line[..idx]) {
+                Ok(s) => self.received(s, ctx),
+                Err(_) => self.failed(ClientError::Protocol("Invalid utf-8".to_owned()), ctx),
+            }
+            if self.closed {
+                return
+            }
+        }
+    }
+
+    fn error(&mut self, err: PayloadError, ctx: &mut Context<Self>) -> Running {
+        debug!("Response error: {}", err);
+        // stream finishes after error, it must not start new request
+        self.transport = None;
+        self.next(ctx);
+        Running::Continue
+    }
+
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        // polling request is complete or streaming request reached max size
+        match self.transport {
+            Some(transport) if !self.closed => self.poll(transport, ctx),
+            _ => (),
+        }
+    }
+}
+
+/// Websocket transport frames
+impl StreamHandler<ws::Message, ws::ProtocolError> for Connection {
+
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Context<Self>) {
+        match msg {
+            ws::Message::Text(text) => self.received(&text, ctx),
+            ws::Message::Ping(msg) => {
+                if let Some(ref mut writer) = self.ws {
+                    writer.pong(&msg);
+                }
+            }
+            ws::Message::Close(_) => self.lost(ClientError::Disconnected, ctx),
+            _ => (),
+        }
+    }
+
+    fn error(&mut self, err: ws::ProtocolError, ctx: &mut Context<Self>) -> Running {
+        debug!("Websocket error: {}", err);
+        self.lost(ClientError::Disconnected, ctx);
+        Running::Continue
+    }
+
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        self.lost(ClientError::Disconnected, ctx);
+    }
+}
@@ -0,0 +1,186 @@
+//! SockJS client
+//!
+//! Client performs `/info` handshake, picks first transport that server
+//! supports (websocket, xhr streaming or xhr polling) and falls back to
+//! the next one if transport can not be opened. Received messages are
+//! available as a stream of `ClientEvent`, messages are sent with
+//! `ClientWriter`, which is also a sink. Available with `client` feature.
+//!
+//! Client has to be started from running actix system.
+//!
+//! ```rust,ignore
+//! let (reader, writer) = SockJSClient::new("http://127.0.0.1:8080/echo")
+//!     .reconnect(3, Duration::from_secs(1))
+//!     .connect();
+//! writer.send("hello");
+//! Arbiter::handle().spawn(reader.for_each(|ev| {
+//!     println!("{:?}", ev);
+//!     Ok(())
+//! }).map_err(|_| ()));
+//! ```
+use std::fmt;
+use std::time::Duration;
+
+use actix::{Actor, Addr, Syn};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+
+use protocol::CloseCode;
+
+mod connection;
+
+use self::connection::{Connection, Shutdown, Outgoing};
+
+/// Client transport
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClientTransport {
+    Websocket,
+    XhrStreaming,
+    XhrPolling,
+}
+
+impl ClientTransport {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ClientTransport::Websocket => "websocket",
+            ClientTransport::XhrStreaming => "xhr_streaming",
+            ClientTransport::XhrPolling => "xhr",
+        }
+    }
+}
+
+/// Event received from server
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    /// Session is opened with transport
+    Open(ClientTransport),
+    Message(String),
+    /// Connection is lost, client opens new session
+    Reconnecting,
+    /// Session is closed by server
+    Close(CloseCode),
+}
+
+/// Client error
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientError {
+    /// `/info` request failed
+    Info(String),
+    /// None of transports can be opened
+    NoTransport,
+    /// Connection is lost and reconnect attempts are exhausted
+    Disconnected,
+    /// Server sent malformed frame
+    Protocol(String),
+    /// Message can not be delivered to server
+    Send(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Info(ref err) => write!(f, "Info request failed: {}", err),
+            ClientError::NoTransport => write!(f, "No transport available"),
+            ClientError::Disconnected => write!(f, "Connection lost"),
+            ClientError::Protocol(ref err) => write!(f, "Protocol error: {}", err),
+            ClientError::Send(ref err) => write!(f, "Can not send message: {}", err),
+        }
+    }
+}
+
+/// SockJS client builder
+#[derive(Clone, Debug)]
+pub struct SockJSClient {
+    url: String,
+    transports: Vec<ClientTransport>,
+    attempts: usize,
+    delay: Duration,
+}
+
+impl SockJSClient {
+    /// Create client for sockjs application at `url`,
+    /// i.e. `http://127.0.0.1:8080/echo`
+    pub fn new<U: Into<String>>(url: U) -> SockJSClient {
+        SockJSClient {
+            url: url.into().trim_right_matches('/').to_owned(),
+            transports: vec![ClientTransport::Websocket,
+                             ClientTransport::XhrStreaming,
+                             ClientTransport::XhrPolling],
+            attempts: 0,
+            delay: Duration::new(1, 0),
+        }
+    }
+
+    /// Set transports in order of preference.
+    ///
+    /// By default websocket, xhr streaming and xhr polling are used.
+    pub fn transports(mut self, transports: Vec<ClientTransport>) -> Self {
+        self.transports = transports;
+        self
+    }
+
+    /// Open new session if connection is lost, at most `attempts` times
+    /// in a row, waiting `delay` between attempts.
+    ///
+    /// Sessions closed by server are not reopened. By default client
+    /// does not reconnect.
+    pub fn reconnect(mut self, attempts: usize, delay: Duration) -> Self {
+        self.attempts = attempts;
+        self.delay = delay;
+        self
+    }
+
+    /// Start client, messages sent before session is opened are queued
+    pub fn connect(self) -> (ClientReader, ClientWriter) {
+        let (tx, rx) = unbounded();
+        let addr: Addr<Syn, _> = Connection::new(self, tx).start();
+        (ClientReader(rx), ClientWriter(addr))
+    }
+}
+
+/// Stream of events received from server
+pub struct ClientReader(UnboundedReceiver<Result<ClientEvent, ClientError>>);
+
+impl Stream for ClientReader {
+    type Item = ClientEvent;
+    type Error = ClientError;
+
+    fn poll(&mut self) -> Poll<Option<ClientEvent>, ClientError> {
+        match self.0.poll() {
+            Ok(Async::Ready(Some(Ok(ev)))) => Ok(Async::Ready(Some(ev))),
+            Ok(Async::Ready(Some(Err(err)))) => Err(err),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(None)) | Err(_) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+/// Sends messages to server
+#[derive(Clone)]
+pub struct ClientWriter(Addr<Syn, Connection>);
+
+impl ClientWriter {
+    /// Send message
+    pub fn send<M: Into<String>>(&self, msg: M) {
+        self.0.do_send(Outgoing(msg.into()));
+    }
+
+    /// Close session
+    pub fn close(&self) {
+        self.0.do_send(Shutdown);
+    }
+}
+
+impl Sink for ClientWriter {
+    type SinkItem = String;
+    type SinkError = ClientError;
+
+    fn start_send(&mut self, msg: String) -> StartSend<String, ClientError> {
+        self.send(msg);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), ClientError> {
+        Ok(Async::Ready(()))
+    }
+}
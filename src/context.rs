@@ -1,61 +1,68 @@
-use std;
+use std::marker::PhantomData;
 use std::sync::Arc;
-use std::collections::VecDeque;
+use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
 
 use actix::dev::*;
 use actix::{ActorState, Message as ActixMessage};
 
-use serde_json;
-use futures::{Async, Future, Poll, Stream};
-use futures::sync::oneshot::Sender;
-use futures::sync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
+use serde::Serialize;
+use serde_json::{self, Value};
+use futures::{future, Async, Future, Poll, Stream};
+use futures::sync::oneshot::{self, Sender};
+use futures::sync::mpsc::{UnboundedSender, UnboundedReceiver};
 
 use session::{Message, Session, CloseReason};
 use protocol::{CloseCode, Frame};
-use manager::{SockJSManager, Broadcast};
-
+use manager::{SockJSManager, Broadcast, Buffered, RecordEntry, SendMessage};
+use metrics::Metrics;
+use presence::{Track, Join, Leave};
+use rpc::{self, RpcError};
+use spans::{self, Span};
+use topics::{Publication, Publish, SessionTopic};
+
+/// Lifecycle events, peer messages and frames sent by the manager,
+/// session context handles them in the order they were sent
 #[derive(Debug)]
 pub enum SockJSChannel {
     Opened,
-    Acquired(UnboundedSender<ChannelItem>),
+    /// Transport acquired session, buffered frames are flushed
+    /// within transport request span
+    Acquired(UnboundedSender<ChannelItem>, Span),
+    Resumed,
     Released,
+    Message(Message),
+    /// Broadcast, publication or message sent to the session,
+    /// queued with frames sent by the session itself
+    Frame(Arc<Frame>),
     Closed(CloseReason),
 }
 
 #[derive(Message, Debug)]
 pub enum ChannelItem {
-    Frame(Frame),
+    Frame(RecordEntry),
     Ready,
 }
 
-#[derive(Debug)]
-enum BufItem {
-    Message(String),
-    Messages(Vec<String>),
-    Frame(Frame),
-}
-
-impl BufItem {
-    fn is_msg(&self) -> bool {
-        match *self {
-            BufItem::Message(_) | BufItem::Messages(_) => true,
-            _ => false,
-        }
-    }
-}
-
 
 /// Sockjs session context
 pub struct SockJSContext<A> where A: Session, A::Context: AsyncContext<A>
 {
     inner: ContextImpl<A>,
     sid: Arc<String>,
-    rx: UnboundedReceiver<SockJSChannel>,
     tx: Option<UnboundedSender<ChannelItem>>,
-    buf: VecDeque<BufItem>,
+    /// Frames sent while transport is not connected
+    buf: VecDeque<RecordEntry>,
     sm: Addr<Syn, SockJSManager<A>>,
+    /// Pending rpc calls and their timeouts
+    calls: HashMap<u64, (Sender<Result<Value, RpcError>>, SpawnHandle)>,
+    call_id: u64,
+    metrics: Metrics,
 }
 
+/// Pending rpc call made with `SockJSContext::call()`
+pub type RpcCall = Box<Future<Item=Value, Error=RpcError>>;
+
 impl<A> ActorContext for SockJSContext<A> where A: Session<Context=Self>
 {
     /// Stop actor execution
@@ -89,7 +96,7 @@ impl<A> AsyncContext<A> for SockJSContext<A> where A: Session<Context=Self>
     }
 
     #[doc(hidden)]
-        #[inline]
+    #[inline]
     fn waiting(&self) -> bool {
         self.inner.waiting() || self.inner.state() == ActorState::Stopping ||
             self.inner.state() == ActorState::Stopped
@@ -134,27 +141,140 @@ impl<A> SockJSContext<A> where A: Session<Context=Self>
         self.sm.do_send(Broadcast::new(Frame::Message(message.into().0)));
     }
 
+    /// Send message to specific session
+    pub fn send_to<M>(&mut self, sid: &str, message: M) where M: Into<Message> {
+        self.sm.do_send(SendMessage{sid: sid.to_owned(), msg: message.into()});
+    }
+
+    /// Attach user id and metadata to session, for presence tracking
+    ///
+    /// User stays online while at least one of its sessions is open.
+    pub fn track<T: Into<String>>(&mut self, user: T, meta: Value) {
+        self.sm.do_send(Track{sid: Arc::clone(&self.sid), user: user.into(), meta});
+    }
+
+    /// Join tracked session to the room
+    pub fn join<T: Into<String>>(&mut self, room: T) {
+        self.sm.do_send(Join{sid: Arc::clone(&self.sid), room: room.into()});
+    }
+
+    /// Remove tracked session from the room
+    pub fn leave<T: Into<String>>(&mut self, room: T) {
+        self.sm.do_send(Leave{sid: Arc::clone(&self.sid), room: room.into()});
+    }
+
+    /// Subscribe session to the topic
+    pub fn subscribe<T: Into<String>>(&mut self, topic: T) {
+        self.sm.do_send(SessionTopic{
+            sid: Arc::clone(&self.sid), topic: topic.into(), subscribe: true});
+    }
+
+    /// Unsubscribe session from the topic
+    pub fn unsubscribe<T: Into<String>>(&mut self, topic: T) {
+        self.sm.do_send(SessionTopic{
+            sid: Arc::clone(&self.sid), topic: topic.into(), subscribe: false});
+    }
+
+    /// Publish data to the topic
+    pub fn publish<T: Into<String>>(&mut self, topic: T, data: Value) {
+        self.sm.do_send(Publish(Publication::new(topic, data)));
+    }
+
+    /// Call method on the peer, JSON-RPC 2.0 style
+    ///
+    /// Returned future resolves with call result, with error reported
+    /// by the peer, or with `RpcError::timeout()` if peer does not
+    /// respond within `timeout`. Peer responses have to be passed
+    /// to `Rpc::handle()`.
+    pub fn call<P: Serialize>(&mut self, method: &str, params: P, timeout: Duration)
+                              -> RpcCall
+    {
+        let params = match serde_json::to_value(params) {
+            Ok(params) => params,
+            Err(err) => return Box::new(future::err(RpcError::invalid_params(err.to_string()))),
+        };
+        self.call_id += 1;
+        let id = self.call_id;
+        let (tx, rx) = oneshot::channel();
+        self.send(rpc::request(id, method, params));
+        let handle = self.run_later(
+            timeout, move |_, ctx| ctx.resolve_call(id, Err(RpcError::timeout())));
+        self.calls.insert(id, (tx, handle));
+
+        Box::new(rx.then(|res| match res {
+            Ok(res) => res,
+            Err(_) => Err(RpcError::internal_error("Session is closed")),
+        }))
+    }
+
+    /// Resolve pending rpc call
+    pub(crate) fn resolve_call(&mut self, id: u64, result: Result<Value, RpcError>) {
+        if let Some((tx, handle)) = self.calls.remove(&id) {
+            self.cancel_future(handle);
+            let _ = tx.send(result);
+        }
+    }
+
+    /// Session manager address
+    pub(crate) fn manager(&self) -> &Addr<Syn, SockJSManager<A>> {
+        &self.sm
+    }
+
     /// Close session
     pub fn close(&mut self) {
         self.send_frame(Frame::Close(CloseCode::GoAway));
     }
 
-    fn send_frame(&mut self, frm: Frame) {
-        let msg = if let Some(ref mut tx) = self.tx {
-            match tx.unbounded_send(ChannelItem::Frame(frm)) {
-                Ok(()) => return,
-                Err(err) => {
-                    match err.into_inner() {
-                        ChannelItem::Frame(frm) => frm,
-                        _ => unreachable!()
-                    }
-                }
+    /// Close session with application defined code and reason
+    ///
+    /// Returns error if `code` is not in `3000-4999` range.
+    pub fn close_with(&mut self, code: u16, reason: &str) -> Result<(), ()> {
+        match CloseCode::custom(code, reason) {
+            Some(code) => {
+                self.send_frame(Frame::Close(code));
+                Ok(())
             }
-        } else {
-            frm
+            None => {
+                warn!("Close code has to be in 3000-4999 range: {}", code);
+                Err(())
+            }
+        }
+    }
+
+    fn send_frame(&mut self, frm: Frame) {
+        if let Err(frm) = self.try_send(frm.into()) {
+            let frm = match frm {
+                RecordEntry::Frame(frm) => Arc::new(frm),
+                RecordEntry::Arc(frm) => frm,
+            };
+            // manager keeps frames of idle session for the session store
+            self.sm.do_send(Buffered{sid: Arc::clone(&self.sid), msg: Arc::clone(&frm)});
+            self.buf.push_back(RecordEntry::Arc(frm));
+        }
+    }
+
+    /// Send frame queued by the manager, manager keeps buffered frame itself
+    fn queue(&mut self, frm: Arc<Frame>) {
+        if let Err(frm) = self.try_send(frm.into()) {
+            self.buf.push_back(frm);
+        }
+    }
+
+    /// Send frame to connected transport, returns frame back if transport
+    /// is not connected
+    fn try_send(&mut self, frm: RecordEntry) -> Result<(), RecordEntry> {
+        let res = match self.tx {
+            Some(ref tx) => tx.unbounded_send(ChannelItem::Frame(frm))
+                .map_err(|err| match err.into_inner() {
+                    ChannelItem::Frame(frm) => frm,
+                    _ => unreachable!()
+                }),
+            None => Err(frm),
         };
-        self.add_to_buf(msg);
-        self.tx.take();
+        if res.is_err() {
+            self.tx.take();
+        }
+        res
     }
 
     /// Check if transport is connected
@@ -162,48 +282,122 @@ impl<A> SockJSContext<A> where A: Session<Context=Self>
         self.tx.is_some()
     }
 
-    fn add_to_buf(&mut self, msg: Frame) {
-        let is_msg = if let Some(front) = self.buf.back() {
-            front.is_msg()} else { false };
-
-        if is_msg && msg.is_msg() {
-            let item = self.buf.pop_back().unwrap();
-            let vec = match item {
-                BufItem::Message(m) => {
-                    vec![m, msg.into_message()]
-                },
-                BufItem::Messages(mut vec) => {
-                    vec.push(msg.into_message());
-                    vec
-                },
-                _ => unreachable!(),
+    /// Send buffered frames to the transport, consecutive message
+    /// frames are sent as one message array frame
+    fn flush(&mut self, tx: &UnboundedSender<ChannelItem>, span: &Span) {
+        self.metrics.buffer_depth(self.buf.len());
+        spans::flush(span, self.buf.len());
+        let is_msg = |frm: Option<&RecordEntry>| frm.map(|frm| frm.is_msg()).unwrap_or(false);
+
+        while let Some(frm) = self.buf.pop_front() {
+            let frm = if frm.is_msg() && is_msg(self.buf.front()) {
+                let mut batch = vec![frm];
+                while is_msg(self.buf.front()) {
+                    batch.extend(self.buf.pop_front());
+                }
+                let msgs: Vec<&str> = batch.iter()
+                    .map(|frm| match **frm {
+                        Frame::Message(ref msg) => msg.as_str(),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                RecordEntry::from(Frame::MessageVec(serde_json::to_string(&msgs).unwrap()))
+            } else {
+                frm
             };
-            self.buf.push_back(BufItem::Messages(vec));
-        } else if msg.is_msg() {
-            self.buf.push_back(BufItem::Message(msg.into_message()));
-        } else {
-            self.buf.push_back(BufItem::Frame(msg));
+            let _ = tx.unbounded_send(ChannelItem::Frame(frm));
         }
     }
 }
 
 impl<A> SockJSContext<A> where A: Session<Context=Self>
 {
-    pub(crate) fn start(session: A, sid: Arc<String>, addr: Addr<Syn, SockJSManager<A>>)
-                        -> (Addr<Syn, A>, UnboundedSender<SockJSChannel>)
+    /// Start session in current arbiter
+    pub(crate) fn start(session: A, sid: Arc<String>, addr: Addr<Syn, SockJSManager<A>>,
+                        rx: UnboundedReceiver<SockJSChannel>, metrics: Metrics)
     {
-        let (tx, rx) = unbounded();
-
         let mut ctx = SockJSContext {
-            sid, rx,
+            sid,
             inner: ContextImpl::new(Some(session)),
             tx: None,
             buf: VecDeque::new(),
             sm: addr,
+            calls: HashMap::new(),
+            call_id: 0,
+            metrics,
         };
-        let addr = ctx.address();
+        ctx.inner.spawn(Channel{rx, act: PhantomData});
         Arbiter::handle().spawn(ctx);
-        (addr, tx)
+    }
+
+    /// Handle lifecycle event or peer message
+    fn dispatch(&mut self, act: &mut A, item: SockJSChannel) {
+        match item {
+            SockJSChannel::Opened => act.opened(self),
+            SockJSChannel::Acquired(tx, span) => {
+                self.flush(&tx, &span);
+                let _ = tx.unbounded_send(ChannelItem::Ready);
+                self.tx = Some(tx);
+                act.acquired(self);
+            }
+            SockJSChannel::Resumed => act.resumed(self),
+            SockJSChannel::Released => {
+                self.tx.take();
+                act.released(self);
+            }
+            SockJSChannel::Message(msg) => {
+                let _ = <A as Handler<Message>>::handle(act, msg, self);
+            }
+            SockJSChannel::Frame(frm) => self.queue(frm),
+            SockJSChannel::Closed(reason) => {
+                self.tx.take();
+                act.closed(self, reason);
+                self.stop();
+            }
+        }
+    }
+}
+
+/// Session channel, registered as context stream, the same way
+/// `AsyncContext::add_stream()` does it. Lifecycle callbacks and peer
+/// messages are handled by the actor in the order manager sent them,
+/// channel completes after `closed()`, nothing is delivered afterwards.
+struct Channel<A> {
+    rx: UnboundedReceiver<SockJSChannel>,
+    act: PhantomData<A>,
+}
+
+impl<A> ActorFuture for Channel<A> where A: Session<Context=SockJSContext<A>>
+{
+    type Item = ();
+    type Error = ();
+    type Actor = A;
+
+    fn poll(&mut self, act: &mut A, ctx: &mut SockJSContext<A>) -> Poll<(), ()> {
+        loop {
+            match self.rx.poll() {
+                Ok(Async::Ready(Some(item))) => {
+                    let closed = match item {
+                        SockJSChannel::Closed(_) => true,
+                        _ => false,
+                    };
+                    ctx.dispatch(act, item);
+                    if closed {
+                        return Ok(Async::Ready(()))
+                    }
+                    // callback started waiting future, continue after it completes
+                    if ctx.waiting() {
+                        return Ok(Async::NotReady)
+                    }
+                }
+                Ok(Async::Ready(None)) | Err(_) => {
+                    // manager is gone
+                    ctx.stop();
+                    return Ok(Async::Ready(()))
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            }
+        }
     }
 }
 
@@ -214,64 +408,12 @@ impl<A> Future for SockJSContext<A> where A: Session<Context=Self>
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // `ContextImpl` polls actor with context that owns it,
+        // same as actix's own `Context` does
         let ctx: &mut SockJSContext<A> = unsafe {
-            std::mem::transmute(self as &mut SockJSContext<A>)
+            &mut *(self as *mut SockJSContext<A>)
         };
-
-        match self.inner.poll(ctx) {
-            Ok(Async::NotReady) => {
-                // sockjs channel
-                loop {
-                    match self.rx.poll() {
-                        Ok(Async::Ready(Some(msg))) => {
-                            match msg {
-                                SockJSChannel::Opened => {
-                                    self.inner.actor().opened(ctx);
-                                },
-                                SockJSChannel::Acquired(tx) => {
-                                    while let Some(msg) = self.buf.pop_front() {
-                                        match msg {
-                                            BufItem::Message(msg) => {
-                                                let _ = tx.unbounded_send(
-                                                    ChannelItem::Frame(Frame::Message(msg)));
-                                            },
-                                            BufItem::Messages(msg) => {
-                                                let _ = tx.unbounded_send(
-                                                    ChannelItem::Frame(
-                                                        Frame::MessageVec(
-                                                            serde_json::to_string(&msg).unwrap())));
-                                            },
-                                            BufItem::Frame(msg) => {
-                                                let _ = tx.unbounded_send(ChannelItem::Frame(msg));
-                                            },
-                                        }
-                                    };
-                                    let _ = tx.unbounded_send(ChannelItem::Ready);
-                                    self.tx = Some(tx);
-                                    self.inner.actor().acquired(ctx);
-                                }
-                                SockJSChannel::Released => {
-                                    self.tx.take();
-                                    self.inner.actor().released(ctx);
-                                },
-                                SockJSChannel::Closed(reason) => {
-                                    self.tx.take();
-                                    self.inner.actor().closed(ctx, reason);
-                                    self.stop()
-                                }
-                            }
-                            continue
-                        },
-                        Ok(Async::Ready(None)) | Ok(Async::NotReady) =>
-                            break,
-                        Err(_) => {},
-                    }
-                }
-                Ok(Async::NotReady)
-            },
-            Ok(Async::Ready(())) => Ok(Async::Ready(())),
-            Err(()) => Err(())
-        }
+        self.inner.poll(ctx)
     }
 }
 
@@ -24,16 +24,47 @@ extern crate http;
 #[macro_use]
 extern crate actix;
 extern crate actix_web;
+#[cfg(any(feature = "permessage-deflate", feature = "compress"))]
+extern crate flate2;
+#[cfg(feature = "tracing")]
+#[macro_use(span, event)]
+extern crate tracing;
 
+mod bus;
+#[cfg(feature = "client")]
+mod client;
 mod context;
 mod application;
 mod manager;
+mod metrics;
+mod presence;
+mod rpc;
 mod session;
+mod sharded;
+mod spans;
+#[cfg(feature = "stomp")]
+mod stomp;
+mod store;
+mod topics;
 mod utils;
 mod protocol;
 mod transports;
 
 pub use application::SockJS;
-pub use context::SockJSContext;
-pub use manager::SockJSManager;
-pub use session::{Message, Session, CloseReason};
+#[cfg(feature = "permessage-deflate")]
+pub use transports::DeflateConfig;
+pub use context::{SockJSContext, RpcCall};
+#[cfg(feature = "client")]
+pub use client::{SockJSClient, ClientEvent, ClientError, ClientTransport, ClientReader, ClientWriter};
+pub use bus::{BroadcastBus, BusMessage, MemoryBus, NetBus};
+pub use manager::{SockJSManager, CreateSession, Disconnect, DisconnectWhere, Placement, SendMessage,
+                  SessionEvent, SessionInfo, Subscribe};
+pub use presence::{Roster, PresenceUser};
+pub use protocol::CloseCode;
+pub use rpc::{Rpc, RpcError};
+pub use session::{Message, Session, SessionError, CloseReason};
+pub use sharded::ShardedManager;
+pub use store::{SessionStore, StoredSession, StoredFrame, MemoryStore, FileStore};
+pub use topics::{Publication, Publish, SubscribeTopic};
+#[cfg(feature = "stomp")]
+pub use stomp::{StompCodec, StompCommand, StompError, StompFrame, StompItem, StompSession};
@@ -1,27 +1,51 @@
 use std::sync::Arc;
 use std::ops::Deref;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::time::{Instant, Duration};
 use futures::sync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
 
 use actix::*;
 use actix::Message as ActixMessage;
-use protocol::Frame;
+use rand::{self, Rng};
+use serde_json::Value;
+
+use bus::{BroadcastBus, BusMessage};
+use metrics::{Metrics, Registry};
+use presence::{Presence, PresenceUser, Track, Join, Leave, Roster};
+use topics::{ActorTopic, Envelope, Publication, Publish, SessionTopic, SubscribeTopic,
+             TopicRouter};
+use spans;
+use protocol::{CloseCode, Frame};
+use store::{SessionStore, StoredFrame, StoredSession};
 use context::{SockJSContext, SockJSChannel, ChannelItem};
 use session::{Message, Session, SessionState, SessionError, CloseReason};
 
 #[doc(hidden)]
 pub trait SessionManager<S>: Actor<Context=Context<Self>> +
-    Handler<Acquire> + Handler<Release> + Handler<SessionMessage> {}
+    Handler<Acquire> + Handler<Release> + Handler<SessionMessage> + Handler<GetMetrics> {}
 
 /// Acquire message
 pub struct Acquire {
     sid: Arc<String>,
-    addr: Recipient<Syn, Broadcast>,
+    transport: &'static str,
+    ack: Option<u64>,
 }
 impl Acquire {
-    pub fn new(sid: String, addr: Recipient<Syn, Broadcast>) -> Self {
-        Acquire{addr, sid: Arc::new(sid)}
+    pub fn new(sid: String, transport: &'static str) -> Self {
+        Acquire{transport, sid: Arc::new(sid), ack: None}
+    }
+
+    /// Sequence number of last message frame received by peer,
+    /// reported by polling transports only
+    pub fn ack(mut self, seq: Option<u64>) -> Self {
+        self.ack = seq;
+        self
+    }
+
+    pub(crate) fn sid(&self) -> &str {
+        &self.sid
     }
 }
 
@@ -40,12 +64,22 @@ pub struct Release {
 pub struct SessionMessage {
     pub sid: Arc<String>,
     pub msg: Message,
+    /// Transport that received message
+    pub transport: &'static str,
 }
 
 impl ActixMessage for SessionMessage {
     type Result = Result<(), ()>;
 }
 
+/// Query metrics registry of the manager
+#[doc(hidden)]
+pub struct GetMetrics;
+
+impl ActixMessage for GetMetrics {
+    type Result = Result<Metrics, ()>;
+}
+
 /// Broadcast message to all sessions
 #[derive(Debug, Message)]
 pub struct Broadcast {
@@ -64,6 +98,140 @@ impl Clone for Broadcast {
     }
 }
 
+/// Send message to specific session
+///
+/// If session is not owned by this manager, message get published
+/// to the broadcast bus.
+#[derive(Debug, Message)]
+pub struct SendMessage {
+    pub sid: String,
+    pub msg: Message,
+}
+
+/// Frame buffered by session context while transport is not connected
+///
+/// Manager keeps it with idle session record for the session store.
+#[doc(hidden)]
+#[derive(Debug, Message)]
+pub struct Buffered {
+    pub sid: Arc<String>,
+    pub msg: Arc<Frame>,
+}
+
+/// Create session before peer connects
+///
+/// Session actor starts immediately, `Session::opened()` get called
+/// and messages sent by the session get buffered until peer connects
+/// with returned session id. Random session id is generated if `sid`
+/// is not set. Session that peer does not connect to expires after
+/// disconnect delay. `SessionEvent::Opened` is reported with `"server"`
+/// transport.
+#[derive(Debug, Default)]
+pub struct CreateSession {
+    pub sid: Option<String>,
+}
+
+impl ActixMessage for CreateSession {
+    type Result = Result<String, SessionError>;
+}
+
+/// Close session with application defined close code and reason
+///
+/// Transport that holds the session gets close frame, later requests
+/// for the session get the same close frame. `Session::closed()` get called
+/// with `CloseReason::Kicked` that carries the close code. Fails if session is not found or closed
+/// already, or if code is not in `3000-4999` range.
+#[derive(Debug)]
+pub struct Disconnect {
+    pub sid: String,
+    pub code: u16,
+    pub reason: String,
+}
+
+impl ActixMessage for Disconnect {
+    type Result = Result<(), ()>;
+}
+
+/// Session attributes available to `DisconnectWhere` filter
+#[derive(Debug)]
+pub struct SessionInfo<'a> {
+    pub sid: &'a str,
+    /// User id attached with `SockJSContext::track()`
+    pub user: Option<&'a str>,
+    /// Name of the transport that holds session
+    pub transport: Option<&'static str>,
+}
+
+/// Disconnect every session that matches filter, see `Disconnect`
+///
+/// Result is number of disconnected sessions.
+pub struct DisconnectWhere {
+    pub filter: Arc<Fn(&SessionInfo) -> bool + Send + Sync>,
+    pub code: u16,
+    pub reason: String,
+}
+
+impl DisconnectWhere {
+    pub fn new<F, R>(filter: F, code: u16, reason: R) -> DisconnectWhere
+        where F: Fn(&SessionInfo) -> bool + Send + Sync + 'static, R: Into<String>
+    {
+        DisconnectWhere{filter: Arc::new(filter), code, reason: reason.into()}
+    }
+
+    /// Disconnect all sessions of the user
+    pub fn user<U, R>(user: U, code: u16, reason: R) -> DisconnectWhere
+        where U: Into<String>, R: Into<String>
+    {
+        let user = user.into();
+        DisconnectWhere::new(move |s| s.user == Some(user.as_str()), code, reason)
+    }
+}
+
+impl Clone for DisconnectWhere {
+    fn clone(&self) -> DisconnectWhere {
+        DisconnectWhere {
+            filter: Arc::clone(&self.filter),
+            code: self.code,
+            reason: self.reason.clone(),
+        }
+    }
+}
+
+impl ActixMessage for DisconnectWhere {
+    type Result = Result<usize, ()>;
+}
+
+/// Generate random session id
+pub(crate) fn new_sid() -> String {
+    rand::thread_rng().gen_ascii_chars().take(16).collect()
+}
+
+/// Session lifecycle event
+///
+/// Events are delivered to observers registered with
+/// `SockJSManager::subscribe()` or `Subscribe` message.
+#[derive(Clone, Debug, Message)]
+pub enum SessionEvent {
+    /// New session get opened by transport
+    Opened { sid: String, transport: &'static str },
+    /// Transport acquired session
+    TransportAcquired { sid: String, transport: &'static str },
+    /// Transport released session
+    TransportReleased { sid: String },
+    /// Session get closed
+    Closed { sid: String, reason: CloseReason },
+    /// Idle session get expired and removed from manager
+    Expired { sid: String },
+    /// First session of the user arrived, globally or to the room
+    Joined { user: String, room: Option<String>, meta: Value },
+    /// Last session of the user left, globally or the room
+    Left { user: String, room: Option<String> },
+}
+
+/// Register session lifecycle observer
+#[derive(Message)]
+pub struct Subscribe(pub Recipient<Syn, SessionEvent>);
+
 #[derive(Debug)]
 pub enum RecordEntry {
     Frame(Frame),
@@ -118,56 +286,248 @@ pub struct Record {
     pub buffer: VecDeque<RecordEntry>,
     /// Channel to context
     tx: UnboundedSender<SockJSChannel>,
+    /// Close reason reported by transport
+    reason: Option<CloseReason>,
+    /// Message frames sent to peer but not acknowledged yet
+    replay: VecDeque<(u64, RecordEntry)>,
+    /// Replay window size, `0` disables reliable delivery
+    window: usize,
+    /// Sequence number of last message frame sent to peer
+    seq: u64,
+    /// Session span
+    span: spans::Span,
+    /// Span of transport request that holds session
+    request: spans::Span,
+    /// Transport delivers peer messages to session context directly
+    direct: bool,
+    /// Close frame code for closed session
+    code: Option<CloseCode>,
+    /// Metrics of the manager that owns session
+    metrics: Metrics,
 }
 
 impl Record {
-    fn new(sid: Arc<String>, tx: UnboundedSender<SockJSChannel>) -> Record {
+    fn new(sid: Arc<String>, tx: UnboundedSender<SockJSChannel>, window: usize,
+           metrics: Metrics) -> Record {
+        let span = spans::session(&sid);
         Record {
-            sid, tx,
+            sid, tx, window, metrics,
             state: SessionState::New,
             buffer: VecDeque::new(),
+            reason: None,
+            replay: VecDeque::new(),
+            seq: 0,
+            span: span.clone(),
+            request: span,
+            direct: false,
+            code: None,
+        }
+    }
+
+    /// Deliver peer message directly to session context.
+    ///
+    /// Message is returned if manager has to inspect peer messages,
+    /// i.e. topics are enabled, in that case it has to be sent
+    /// to manager with `SessionMessage`.
+    pub fn deliver(&self, msg: Message) -> Result<(), Message> {
+        if !self.direct {
+            return Err(msg)
+        }
+        match self.tx.unbounded_send(SockJSChannel::Message(msg)) {
+            Ok(()) => Ok(()),
+            Err(err) => match err.into_inner() {
+                SockJSChannel::Message(msg) => Err(msg),
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Metrics of the manager that owns session
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Span of transport request that holds session,
+    /// or session span if session is not acquired
+    pub fn span(&self) -> &spans::Span {
+        &self.request
+    }
+
+    /// Change session state
+    pub fn set_state(&mut self, state: SessionState) {
+        if self.state != state {
+            spans::state(&self.span, &self.state, &state);
+            self.state = state;
         }
     }
 
     pub fn close(&mut self) {
-        self.state = SessionState::Closed;
+        self.set_state(SessionState::Closed);
+    }
+
+    /// Close frame got sent to the peer, later requests get the same frame
+    pub fn closed_with(&mut self, code: &CloseCode) {
+        self.set_state(SessionState::Closed);
+        self.code = Some(code.clone());
+    }
+
+    /// Session get disconnected by server
+    pub fn kick(&mut self, code: CloseCode) {
+        self.set_state(SessionState::Closed);
+        self.reason = Some(CloseReason::Kicked(code.clone()));
+        self.code = Some(code);
+    }
+
+    /// Close frame code for closed session
+    pub fn close_code(&self) -> CloseCode {
+        self.code.clone().unwrap_or(CloseCode::GoAway)
     }
 
     pub fn interrupted(&mut self) {
         if self.state == SessionState::Running {
-            self.state = SessionState::Interrupted;
+            self.set_state(SessionState::Interrupted);
+        }
+    }
+
+    /// Peer closed session
+    pub fn peer_closed(&mut self, code: Option<u16>, reason: Option<String>) {
+        if self.state != SessionState::Closed {
+            self.set_state(SessionState::Closed);
+            self.reason = Some(CloseReason::Peer{code, reason});
+        }
+    }
+
+    /// Transport protocol error
+    pub fn protocol_error<T: Into<String>>(&mut self, err: T) {
+        if self.state == SessionState::Running {
+            self.set_state(SessionState::Interrupted);
+            self.reason = Some(CloseReason::ProtocolError(err.into()));
         }
     }
 
     pub fn add<F: Into<RecordEntry>>(&mut self, frm: F) {
         self.buffer.push_back(frm.into())
     }
+
+    /// Keep frame written to the peer in replay window,
+    /// message frame gets next sequence number
+    pub fn sent<F: Into<RecordEntry>>(&mut self, frm: F) {
+        if self.window == 0 {
+            return
+        }
+        let frm = frm.into();
+        match *frm {
+            Frame::Message(_) | Frame::MessageVec(_) | Frame::MessageBlob(_) => {
+                self.seq += 1;
+                self.replay.push_back((self.seq, frm));
+                if self.replay.len() > self.window {
+                    self.replay.pop_front();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Peer acknowledged message frames up to `seq`.
+    ///
+    /// Frames that are not acknowledged get scheduled for retransmission.
+    pub fn ack(&mut self, seq: u64) {
+        if seq >= self.seq {
+            self.replay.clear();
+            return
+        }
+        if let Some(&(first, _)) = self.replay.front() {
+            if seq + 1 < first {
+                warn!("Session {}: frames {}-{} are out of replay window",
+                      self.sid, seq + 1, first - 1);
+            }
+        }
+        while let Some((num, frm)) = self.replay.pop_back() {
+            if num <= seq {
+                break
+            }
+            self.buffer.push_front(frm);
+        }
+        self.replay.clear();
+        self.seq = seq;
+    }
 }
 
-struct Entry<S: Session> {
-    addr: Addr<Syn, S>,
+struct Entry {
+    /// Channel to session context
+    channel: UnboundedSender<SockJSChannel>,
+    /// Session record, `None` while transport holds session
     record: Option<Record>,
-    transport: Option<Recipient<Syn, Broadcast>>,
+    /// Name of the transport that acquired session
+    transport_name: Option<&'static str>,
     /// heartbeat
     tick: Instant,
+    /// Time session got interrupted, session can be resumed
+    interrupted: Option<Instant>,
+    /// Session context is notified about close
+    closed: bool,
+    /// Close code of session disconnected by server
+    kicked: Option<CloseCode>,
+    /// Frames buffered by session context while transport is not connected,
+    /// queued by manager or sent by the session, kept for session store only
+    pending: Vec<Arc<Frame>>,
+}
+
+impl Entry {
+    /// Queue frame to session context, context sends it to transport
+    /// after frames that are queued already, or buffers it if transport
+    /// is not connected.
+    ///
+    /// Returns `true` if frame get buffered.
+    fn deliver(&mut self, msg: &Broadcast, keep: bool) -> bool {
+        let _ = self.channel.unbounded_send(SockJSChannel::Frame(Arc::clone(&msg.msg)));
+        if self.record.is_some() {
+            if keep {
+                self.pending.push(Arc::clone(&msg.msg));
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Assignment of session actors to arbiter pool
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placement {
+    /// Sessions are assigned to arbiters in turn
+    RoundRobin,
+    /// Arbiter is selected by session id hash
+    Hash,
 }
 
 /// Session manager
 pub struct SockJSManager<S: Session> {
     idle: HashSet<Arc<String>>,
-    sessions: HashMap<Arc<String>, Entry<S>>,
-    factory: Box<Fn() -> S + Sync + Send>,
+    sessions: HashMap<Arc<String>, Entry>,
+    factory: Arc<Fn() -> S + Sync + Send>,
+    /// Size of session arbiter pool and placement strategy
+    arbiters: Option<(usize, Placement)>,
+    pool: Vec<Addr<Syn, Arbiter>>,
+    next: usize,
+    window: usize,
+    resume: Option<Duration>,
+    id: u64,
+    bus: Option<Box<BroadcastBus>>,
+    store: Option<Box<SessionStore>>,
+    delay: Duration,
+    observers: Vec<Recipient<Syn, SessionEvent>>,
+    presence: Presence,
+    router: TopicRouter,
+    topics: bool,
+    metrics: Metrics,
 }
 
 impl<S: Session> SessionManager<S> for SockJSManager<S> {}
 
 impl<S: Session + Default> Default for SockJSManager<S> {
     fn default() -> SockJSManager<S> {
-        SockJSManager {
-            idle: HashSet::new(),
-            sessions: HashMap::new(),
-            factory: Box::new(S::default),
-        }
+        SockJSManager::new(S::default)
     }
 }
 
@@ -177,21 +537,355 @@ impl<S: Session> SockJSManager<S> {
         where F: Sync + Send + 'static + Fn() -> S,
     {
         SockJSManager {
-            factory: Box::new(factory),
+            factory: Arc::new(factory),
+            arbiters: None,
+            pool: Vec::new(),
+            next: 0,
             idle: HashSet::new(),
             sessions: HashMap::new(),
+            window: 0,
+            resume: None,
+            id: rand::random(),
+            bus: None,
+            store: None,
+            delay: Duration::new(10, 0),
+            observers: Vec::new(),
+            presence: Presence::default(),
+            router: TopicRouter::default(),
+            topics: false,
+            metrics: Registry::new(),
+        }
+    }
+
+    /// Route topic envelopes sent by peers.
+    ///
+    /// Peer messages that are subscribe, unsubscribe or publish envelopes
+    /// are handled by the manager and are not delivered to the session.
+    /// Server code can publish to topics with `Publish` message regardless
+    /// of this setting.
+    ///
+    /// By default topic envelopes are delivered to the session as is.
+    pub fn topics(mut self, enable: bool) -> Self {
+        self.topics = enable;
+        self
+    }
+
+    /// Register session lifecycle observer.
+    ///
+    /// Observers can be registered later with `Subscribe` message.
+    pub fn subscribe(mut self, observer: Recipient<Syn, SessionEvent>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Set timeout after which idle session get expired.
+    ///
+    /// By default it is 10 seconds.
+    pub fn disconnect_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Persist idle sessions in session store.
+    ///
+    /// Manager that starts with the same store rehydrates sessions
+    /// when peers reconnect within disconnect delay, and delivers
+    /// frames that were not delivered before restart.
+    pub fn store<T: SessionStore>(mut self, store: T) -> Self {
+        self.store = Some(Box::new(store));
+        self
+    }
+
+    /// Enable reliable delivery over polling transports (`xhr` and `jsonp`)
+    /// with replay window of `size` message frames.
+    ///
+    /// Sequence numbers are not sent on the wire, every message frame
+    /// written to the peer implicitly gets next number, `a[...]` frame
+    /// with several messages counts as one frame, heartbeat, open and
+    /// close frames are not counted. Peer reports number of message
+    /// frames it received so far with `ack` query parameter of the next
+    /// polling request. Frames that are not acknowledged get retransmitted
+    /// and numbered again. Requests without `ack` parameter acknowledge
+    /// everything, so plain sockjs clients keep working. Streaming and
+    /// websocket transports do not support acknowledgements, acquiring
+    /// session with them acknowledges everything too.
+    ///
+    /// By default reliable delivery is disabled.
+    pub fn replay_window(mut self, size: usize) -> Self {
+        self.window = size;
+        self
+    }
+
+    /// Connect manager to the broadcast bus.
+    ///
+    /// Broadcasts and messages for sessions that are not owned by this manager
+    /// get published to the bus, messages from the bus get delivered
+    /// to the manager's sessions.
+    pub fn bus<B: BroadcastBus>(mut self, bus: B) -> Self {
+        self.bus = Some(Box::new(bus));
+        self
+    }
+
+    /// Check if manager is connected to the broadcast bus
+    pub(crate) fn has_bus(&self) -> bool {
+        self.bus.is_some()
+    }
+
+    /// Run session actors on pool of `size` arbiters.
+    ///
+    /// Pool is started with the manager, sessions are assigned
+    /// to arbiters with `placement`. Lifecycle callbacks and
+    /// peer messages are handled in the same order as with sessions
+    /// running in manager's arbiter.
+    ///
+    /// By default sessions run in manager's arbiter.
+    pub fn arbiters(mut self, size: usize, placement: Placement) -> Self {
+        assert!(size > 0, "At least one arbiter is required");
+        self.arbiters = Some((size, placement));
+        self
+    }
+
+    /// Allow to resume interrupted sessions within `timeout`.
+    ///
+    /// Transport that acquires interrupted session within timeout resumes
+    /// it with buffered frames intact, `Session::resumed()` method get called.
+    /// After timeout session get closed with `CloseReason::Interrupted`.
+    /// Timeout should be shorter than session expiration timeout.
+    ///
+    /// By default interrupted sessions are closed immediately,
+    /// as sockjs protocol requires.
+    pub fn resume_timeout(mut self, timeout: Duration) -> Self {
+        self.resume = Some(timeout);
+        self
+    }
+
+    /// Close interrupted session if resume timeout is over
+    fn resume_expired(&mut self, sid: &Arc<String>) {
+        if let Some(timeout) = self.resume {
+            let reason = if let Some(entry) = self.sessions.get_mut(sid) {
+                let expired = match entry.interrupted {
+                    Some(t) => t + timeout <= Instant::now(),
+                    None => false,
+                };
+                if !expired {
+                    return
+                }
+                entry.interrupted.take();
+                entry.closed = true;
+                if let Some(ref mut rec) = entry.record {
+                    let reason = rec.reason.take().unwrap_or(CloseReason::Interrupted);
+                    // later requests get close frame
+                    rec.set_state(SessionState::Closed);
+                    self.metrics.session_closed(&reason);
+                    let _ = rec.tx.unbounded_send(SockJSChannel::Closed(reason.clone()));
+                    Some(reason)
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                self.notify(SessionEvent::Closed{sid: sid.to_string(), reason});
+                self.forget(sid);
+            }
+            self.unpersist(sid);
+        }
+    }
+
+    /// Send lifecycle event to observers
+    fn notify(&mut self, ev: SessionEvent) {
+        self.observers.retain(|o| match o.do_send(ev.clone()) {
+            Err(SendError::Closed(_)) => false,
+            _ => true,
+        });
+    }
+
+    /// Remove closed session from presence roster and topic subscriptions
+    fn forget(&mut self, sid: &Arc<String>) {
+        self.router.remove(sid);
+        for ev in self.presence.untrack(sid) {
+            self.notify(ev);
+        }
+    }
+
+    /// Deliver publication to local subscribers
+    fn deliver_publication(&mut self, msg: &Publication) {
+        let sids = self.router.publish(msg);
+        if !sids.is_empty() {
+            let frm = Broadcast::new(Frame::Message(msg.to_message()));
+            for sid in sids {
+                self.deliver(sid.to_string(), &frm);
+            }
+        }
+    }
+
+    /// Deliver publication to local subscribers and publish it to the bus
+    fn publish(&mut self, msg: Publication) {
+        self.deliver_publication(&msg);
+
+        if let Some(ref bus) = self.bus {
+            bus.publish(BusMessage::Publish{origin: self.id, topic: msg.topic, data: msg.data});
         }
     }
 
+    /// Save idle session to the session store
+    fn persist(&self, sid: &Arc<String>) {
+        if let Some(ref store) = self.store {
+            if let Some(rec) = self.sessions.get(sid).and_then(|e| e.record.as_ref()) {
+                if rec.state == SessionState::Closed {
+                    return
+                }
+                let mut session = StoredSession::new(sid);
+                let pending = self.sessions.get(sid).map(|e| &e.pending[..]).unwrap_or(&[]);
+                session.frames = rec.buffer.iter()
+                    .map(|frm| frm.as_ref())
+                    .chain(pending.iter().map(|frm| frm.as_ref()))
+                    .filter_map(StoredFrame::from_frame)
+                    .collect();
+                if let Err(err) = store.save(&session) {
+                    error!("Can not save session {}: {}", sid, err);
+                }
+            }
+        }
+    }
+
+    /// Remove session from the session store
+    fn unpersist(&self, sid: &str) {
+        if let Some(ref store) = self.store {
+            if let Err(err) = store.remove(sid) {
+                error!("Can not remove session {}: {}", sid, err);
+            }
+        }
+    }
+
+    /// Load session from the session store
+    fn restore(&self, sid: &str) -> Option<StoredSession> {
+        if let Some(ref store) = self.store {
+            match store.load(sid) {
+                Ok(Some(session)) => {
+                    if session.elapsed() <= self.delay.as_secs() {
+                        return Some(session)
+                    }
+                    self.unpersist(sid);
+                }
+                Ok(None) => (),
+                Err(err) => error!("Can not load session {}: {}", sid, err),
+            }
+        }
+        None
+    }
+
+    /// Update live sessions gauges
+    fn report_metrics(&self) {
+        self.metrics.sessions(self.sessions.values().map(|entry| match entry.record {
+            Some(ref rec) => (&rec.state, None),
+            None => (&SessionState::Running, entry.transport_name),
+        }));
+    }
+
+    /// Deliver frame to all sessions
+    fn deliver_all(&mut self, msg: &Broadcast) {
+        let mut buffered = Vec::new();
+        let keep = self.store.is_some();
+        for (sid, entry) in &mut self.sessions {
+            if entry.deliver(msg, keep) {
+                buffered.push(Arc::clone(sid));
+            }
+        }
+        if self.store.is_some() {
+            for sid in buffered {
+                self.persist(&sid);
+            }
+        }
+    }
+
+    /// Deliver frame to specific session, returns `false` if session does not exist
+    fn deliver(&mut self, sid: String, msg: &Broadcast) -> bool {
+        let keep = self.store.is_some();
+        let buffered = if let Some(entry) = self.sessions.get_mut(&sid) {
+            entry.deliver(msg, keep)
+        } else {
+            return false
+        };
+        if buffered {
+            self.persist(&Arc::new(sid));
+        }
+        true
+    }
+
+    /// Start session actor, in arbiter pool if it is configured
+    fn spawn(&mut self, sid: &Arc<String>, ctx: &mut Context<Self>)
+             -> UnboundedSender<SockJSChannel>
+    {
+        let (tx, rx) = unbounded();
+        let addr: Addr<Syn, _> = ctx.address();
+        let sid = Arc::clone(sid);
+
+        let idx = match self.arbiters {
+            None => None,
+            Some((_, Placement::RoundRobin)) => {
+                self.next = (self.next + 1) % self.pool.len();
+                Some(self.next)
+            }
+            Some((_, Placement::Hash)) => {
+                let mut hasher = DefaultHasher::new();
+                sid.hash(&mut hasher);
+                Some((hasher.finish() % self.pool.len() as u64) as usize)
+            }
+        };
+        match idx {
+            Some(idx) => {
+                let factory = Arc::clone(&self.factory);
+                let metrics = Arc::clone(&self.metrics);
+                self.pool[idx].do_send(msgs::Execute::new(move || -> Result<(), ()> {
+                    SockJSContext::start((*factory)(), sid, addr, rx, metrics);
+                    Ok(())
+                }));
+            }
+            None => SockJSContext::start(
+                (*self.factory)(), sid, addr, rx, Arc::clone(&self.metrics)),
+        }
+        tx
+    }
+
+    /// Close session with close frame, session context gets `CloseReason::Kicked`
+    fn kick(&mut self, sid: Arc<String>, code: &CloseCode) -> bool {
+        match self.sessions.get_mut(&sid) {
+            Some(ref mut entry) if !entry.closed => {
+                entry.closed = true;
+                match entry.record {
+                    Some(ref mut rec) => rec.kick(code.clone()),
+                    // transport writes close frame after frames queued to the session,
+                    // record is updated on release
+                    None => {
+                        entry.kicked = Some(code.clone());
+                        let _ = entry.channel.unbounded_send(
+                            SockJSChannel::Frame(Arc::new(Frame::Close(code.clone()))));
+                    }
+                }
+                let _ = entry.channel.unbounded_send(
+                    SockJSChannel::Closed(CloseReason::Kicked(code.clone())));
+            }
+            _ => return false,
+        }
+        let reason = CloseReason::Kicked(code.clone());
+        self.metrics.session_closed(&reason);
+        self.notify(SessionEvent::Closed{sid: sid.to_string(), reason});
+        self.forget(&sid);
+        self.unpersist(&sid);
+        true
+    }
+
     fn hb(&self, ctx: &mut Context<Self>) {
-        ctx.run_later(Duration::new(10, 0), |act, ctx| {
+        ctx.run_later(self.delay, |act, ctx| {
             act.hb(ctx);
 
             let now = Instant::now();
             let mut rem = Vec::new();
             for sid in &act.idle {
                 if let Some(entry) = act.sessions.get(sid) {
-                    if entry.tick + Duration::new(10, 0) < now {
+                    if entry.tick + act.delay < now {
                         rem.push(Arc::clone(sid));
                     }
                 }
@@ -199,11 +893,19 @@ impl<S: Session> SockJSManager<S> {
 
             for sid in rem {
                 act.idle.remove(&sid);
+                act.unpersist(&sid);
                 if let Some(entry) = act.sessions.remove(&sid) {
                     if let Some(rec) = entry.record {
                         let _ = rec.tx.unbounded_send(
                             SockJSChannel::Closed(CloseReason::Expired));
                     }
+                    if !entry.closed {
+                        act.metrics.session_closed(&CloseReason::Expired);
+                        act.notify(SessionEvent::Closed{
+                            sid: sid.to_string(), reason: CloseReason::Expired});
+                    }
+                    act.forget(&sid);
+                    act.notify(SessionEvent::Expired{sid: sid.to_string()});
                 }
             }
         });
@@ -214,8 +916,26 @@ impl<S: Session> Actor for SockJSManager<S> {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Context<Self>) {
+        if let Some(ref bus) = self.bus {
+            let addr: Addr<Syn, _> = ctx.address();
+            bus.subscribe(addr.recipient());
+        }
+        if cfg!(feature = "metrics") {
+            ctx.run_interval(Duration::new(1, 0), |act, _| act.report_metrics());
+        }
+        if let Some((size, _)) = self.arbiters {
+            self.pool = (0..size)
+                .map(|idx| Arbiter::new(format!("sockjs-session-{}", idx)))
+                .collect();
+        }
         self.hb(ctx)
     }
+
+    fn stopped(&mut self, _: &mut Context<Self>) {
+        for arb in self.pool.drain(..) {
+            arb.do_send(msgs::StopArbiter(0));
+        }
+    }
 }
 
 #[doc(hidden)]
@@ -224,29 +944,63 @@ impl<S: Session> Handler<Acquire> for SockJSManager<S> {
 
     fn handle(&mut self, msg: Acquire, ctx: &mut Context<Self>) -> Self::Result {
         if let Some(entry) = self.sessions.get_mut(&msg.sid) {
-            if let Some(rec) = entry.record.take() {
+            if let Some(mut rec) = entry.record.take() {
+                match msg.ack {
+                    Some(seq) => rec.ack(seq),
+                    None => rec.ack(u64::max_value()),
+                }
+                if entry.interrupted.take().is_some() {
+                    rec.set_state(SessionState::Running);
+                    rec.reason.take();
+                    let _ = rec.tx.unbounded_send(SockJSChannel::Resumed);
+                }
+                rec.request = spans::request(&rec.span, msg.transport);
+                spans::acquired(&rec.span, msg.transport);
                 let (tx, rx) = unbounded();
-                let _ = rec.tx.unbounded_send(SockJSChannel::Acquired(tx));
+                let _ = rec.tx.unbounded_send(SockJSChannel::Acquired(tx, rec.request.clone()));
                 self.idle.remove(&msg.sid);
-                entry.transport = Some(msg.addr);
+                // context sends buffered frames to the transport
+                entry.pending.clear();
+                entry.transport_name = Some(msg.transport);
+                self.notify(SessionEvent::TransportAcquired{
+                    sid: msg.sid.to_string(), transport: msg.transport});
                 return Ok((rec, rx))
             } else {
                 return Err(SessionError::Acquired)
             }
         }
-        let (addr, tx) = SockJSContext::start(
-            (*self.factory)(), Arc::clone(&msg.sid), ctx.address());
+        let stored = self.restore(&msg.sid);
+        let tx = self.spawn(&msg.sid, ctx);
         self.sessions.insert(
             Arc::clone(&msg.sid),
-            Entry{addr,
+            Entry{channel: tx.clone(),
                   record: None,
-                  transport: Some(msg.addr),
+                  transport_name: Some(msg.transport),
                   tick: Instant::now(),
+                  interrupted: None,
+                  closed: false,
+                  kicked: None,
+                  pending: Vec::new(),
             });
-        let rec = Record::new(msg.sid, tx);
+        let mut rec = Record::new(msg.sid, tx, self.window, Arc::clone(&self.metrics));
+        // topic envelopes are handled by manager
+        rec.direct = !self.topics;
+        rec.request = spans::request(&rec.span, msg.transport);
+        spans::acquired(&rec.span, msg.transport);
+        if let Some(session) = stored {
+            // session is known to the peer already
+            rec.set_state(SessionState::Running);
+            for frm in session.frames {
+                rec.add(Frame::from(frm));
+            }
+        }
         let (tx, rx) = unbounded();
+        self.metrics.session_opened();
         let _ = rec.tx.unbounded_send(SockJSChannel::Opened);
-        let _ = rec.tx.unbounded_send(SockJSChannel::Acquired(tx));
+        let _ = rec.tx.unbounded_send(SockJSChannel::Acquired(tx, rec.request.clone()));
+        self.notify(SessionEvent::Opened{sid: rec.sid.to_string(), transport: msg.transport});
+        self.notify(SessionEvent::TransportAcquired{
+            sid: rec.sid.to_string(), transport: msg.transport});
         Ok((rec, rx))
     }
 }
@@ -255,21 +1009,69 @@ impl<S: Session> Handler<Acquire> for SockJSManager<S> {
 impl<S: Session> Handler<Release> for SockJSManager<S> {
     type Result = ();
 
-    fn handle(&mut self, msg: Release, _: &mut Context<Self>) {
+    fn handle(&mut self, mut msg: Release, ctx: &mut Context<Self>) {
+        let sid = Arc::clone(&msg.ses.sid);
+        // session got disconnected while transport held it
+        if let Some(code) = self.sessions.get_mut(&sid).and_then(|e| e.kicked.take()) {
+            msg.ses.kick(code);
+        }
+        spans::released(&msg.ses.span, &msg.ses.state);
+        msg.ses.request = msg.ses.span.clone();
+        let closed = match msg.ses.state {
+            SessionState::Closed => true,
+            SessionState::Interrupted => self.resume.is_none(),
+            _ => false,
+        };
+
+        let mut reason = None;
+        let mut released = false;
         if let Some(entry) = self.sessions.get_mut(&msg.ses.sid) {
+            released = true;
             self.idle.insert(Arc::clone(&msg.ses.sid));
+            // session context is notified about close only once
             let _ = match msg.ses.state {
-                SessionState::Closed =>
-                    msg.ses.tx.unbounded_send(
-                        SockJSChannel::Closed(CloseReason::Normal)),
-                SessionState::Interrupted =>
-                    msg.ses.tx.unbounded_send(
-                        SockJSChannel::Closed(CloseReason::Interrupted)),
+                _ if entry.closed => Ok(()),
+                SessionState::Closed => {
+                    let r = msg.ses.reason.take().unwrap_or(CloseReason::Normal);
+                    entry.closed = true;
+                    self.metrics.session_closed(&r);
+                    reason = Some(r.clone());
+                    msg.ses.tx.unbounded_send(SockJSChannel::Closed(r))
+                },
+                SessionState::Interrupted => {
+                    if let Some(timeout) = self.resume {
+                        let sid = Arc::clone(&msg.ses.sid);
+                        entry.interrupted = Some(Instant::now());
+                        ctx.run_later(timeout, move |act, _| act.resume_expired(&sid));
+                        msg.ses.tx.unbounded_send(SockJSChannel::Released)
+                    } else {
+                        let r = msg.ses.reason.take().unwrap_or(CloseReason::Interrupted);
+                        entry.closed = true;
+                        self.metrics.session_closed(&r);
+                        reason = Some(r.clone());
+                        msg.ses.tx.unbounded_send(SockJSChannel::Closed(r))
+                    }
+                },
                 _ => msg.ses.tx.unbounded_send(SockJSChannel::Released)
             };
             entry.tick = Instant::now();
             entry.record = Some(msg.ses);
-            entry.transport.take();
+            entry.transport_name.take();
+        }
+
+        // unknown session was never reported as acquired
+        if released {
+            self.notify(SessionEvent::TransportReleased{sid: sid.to_string()});
+        }
+        if let Some(reason) = reason {
+            self.notify(SessionEvent::Closed{sid: sid.to_string(), reason});
+            self.forget(&sid);
+        }
+
+        if closed {
+            self.unpersist(&sid);
+        } else {
+            self.persist(&sid);
         }
     }
 }
@@ -279,12 +1081,24 @@ impl<S: Session> Handler<SessionMessage> for SockJSManager<S> {
     type Result = Result<(), ()>;
 
     fn handle(&mut self, msg: SessionMessage, _: &mut Context<Self>) -> Self::Result {
+        if !self.sessions.contains_key(&msg.sid) {
+            return Err(())
+        }
+        self.metrics.frames_received(msg.transport, 1, msg.msg.0.len());
+        if self.topics {
+            if let Some(envelope) = Envelope::parse(&msg.msg.0) {
+                match envelope {
+                    Envelope::Subscribe{topic} => self.router.subscribe(&msg.sid, topic),
+                    Envelope::Unsubscribe{topic} => self.router.unsubscribe(&msg.sid, &topic),
+                    Envelope::Publish{topic, data} => self.publish(Publication{topic, data}),
+                }
+                return Ok(())
+            }
+        }
         if let Some(entry) = self.sessions.get_mut(&msg.sid) {
-            entry.addr.do_send(msg.msg);
-            Ok(())
-        } else {
-            Err(())
+            let _ = entry.channel.unbounded_send(SockJSChannel::Message(msg.msg));
         }
+        Ok(())
     }
 }
 
@@ -293,14 +1107,575 @@ impl<S: Session> Handler<Broadcast> for SockJSManager<S> {
     type Result = ();
 
     fn handle(&mut self, msg: Broadcast, _: &mut Context<Self>) {
-        for entry in self.sessions.values_mut() {
-            if let Some(ref tr) = entry.transport {
-                let _ = tr.send(msg.clone());
-                continue
+        self.deliver_all(&msg);
+
+        if let Some(ref bus) = self.bus {
+            if let Frame::Message(ref s) = *msg.msg {
+                bus.publish(BusMessage::Broadcast{origin: self.id, msg: s.clone()});
+            }
+        }
+    }
+}
+
+impl<S: Session> Handler<CreateSession> for SockJSManager<S> {
+    type Result = Result<String, SessionError>;
+
+    fn handle(&mut self, msg: CreateSession, ctx: &mut Context<Self>) -> Self::Result {
+        let sid = Arc::new(msg.sid.unwrap_or_else(new_sid));
+        if self.sessions.contains_key(&sid) {
+            return Err(SessionError::Acquired)
+        }
+        let tx = self.spawn(&sid, ctx);
+        let mut rec = Record::new(
+            Arc::clone(&sid), tx.clone(), self.window, Arc::clone(&self.metrics));
+        rec.direct = !self.topics;
+        self.metrics.session_opened();
+        let _ = rec.tx.unbounded_send(SockJSChannel::Opened);
+        self.sessions.insert(
+            Arc::clone(&sid),
+            Entry{channel: tx,
+                  record: Some(rec),
+                  transport_name: None,
+                  tick: Instant::now(),
+                  interrupted: None,
+                  closed: false,
+                  kicked: None,
+                  pending: Vec::new(),
+            });
+        self.idle.insert(Arc::clone(&sid));
+        self.notify(SessionEvent::Opened{sid: sid.to_string(), transport: "server"});
+        Ok(sid.to_string())
+    }
+}
+
+impl<S: Session> Handler<Disconnect> for SockJSManager<S> {
+    type Result = Result<(), ()>;
+
+    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) -> Self::Result {
+        match CloseCode::custom(msg.code, msg.reason) {
+            Some(code) => if self.kick(Arc::new(msg.sid), &code) { Ok(()) } else { Err(()) },
+            None => {
+                warn!("Close code has to be in 3000-4999 range: {}", msg.code);
+                Err(())
+            }
+        }
+    }
+}
+
+impl<S: Session> Handler<DisconnectWhere> for SockJSManager<S> {
+    type Result = Result<usize, ()>;
+
+    fn handle(&mut self, msg: DisconnectWhere, _: &mut Context<Self>) -> Self::Result {
+        let code = match CloseCode::custom(msg.code, msg.reason) {
+            Some(code) => code,
+            None => {
+                warn!("Close code has to be in 3000-4999 range: {}", msg.code);
+                return Err(())
+            }
+        };
+        let sids: Vec<_> = self.sessions.iter()
+            .filter(|&(sid, entry)| !entry.closed && (msg.filter)(&SessionInfo {
+                sid,
+                user: self.presence.user(sid),
+                transport: entry.transport_name,
+            }))
+            .map(|(sid, _)| Arc::clone(sid))
+            .collect();
+        Ok(sids.into_iter().filter(|sid| self.kick(Arc::clone(sid), &code)).count())
+    }
+}
+
+impl<S: Session> Handler<SendMessage> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendMessage, _: &mut Context<Self>) {
+        let frm = Broadcast::new(Frame::Message(msg.msg.0.clone()));
+        if self.deliver(msg.sid.clone(), &frm) {
+            return
+        }
+
+        if let Some(ref bus) = self.bus {
+            bus.publish(BusMessage::Send{origin: self.id, sid: msg.sid, msg: msg.msg.0});
+        }
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<Buffered> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Buffered, _: &mut Context<Self>) {
+        if self.store.is_none() {
+            return
+        }
+        // transport flushes context queue on acquire
+        let idle = match self.sessions.get_mut(&msg.sid) {
+            Some(ref mut entry) if entry.record.is_some() => {
+                entry.pending.push(msg.msg);
+                true
+            }
+            _ => false,
+        };
+        if idle {
+            self.persist(&msg.sid);
+        }
+    }
+}
+
+impl<S: Session> Handler<Subscribe> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<Self>) {
+        self.observers.push(msg.0);
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<Track> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Track, _: &mut Context<Self>) {
+        if !self.sessions.contains_key(&msg.sid) {
+            return
+        }
+        for ev in self.presence.track(msg.sid, msg.user, msg.meta) {
+            self.notify(ev);
+        }
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<Join> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
+        if let Some(ev) = self.presence.join(&msg.sid, msg.room) {
+            self.notify(ev);
+        }
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<Leave> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _: &mut Context<Self>) {
+        if let Some(ev) = self.presence.leave(&msg.sid, &msg.room) {
+            self.notify(ev);
+        }
+    }
+}
+
+impl<S: Session> Handler<Publish> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Publish, _: &mut Context<Self>) {
+        self.publish(msg.0);
+    }
+}
+
+impl<S: Session> Handler<SubscribeTopic> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeTopic, _: &mut Context<Self>) {
+        self.router.subscribe_actor(msg.topic, msg.subscriber);
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<ActorTopic> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: ActorTopic, _: &mut Context<Self>) {
+        match msg.subscriber {
+            Some(subscriber) => if self.sessions.contains_key(&msg.sid) {
+                self.router.subscribe_session_actor(&msg.sid, msg.topic, subscriber);
+            },
+            None => self.router.unsubscribe_session_actor(&msg.sid, &msg.topic),
+        }
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<SessionTopic> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: SessionTopic, _: &mut Context<Self>) {
+        if msg.subscribe {
+            if self.sessions.contains_key(&msg.sid) {
+                self.router.subscribe(&msg.sid, msg.topic);
+            }
+        } else {
+            self.router.unsubscribe(&msg.sid, &msg.topic);
+        }
+    }
+}
+
+impl<S: Session> Handler<Roster> for SockJSManager<S> {
+    type Result = Result<Vec<PresenceUser>, ()>;
+
+    fn handle(&mut self, msg: Roster, _: &mut Context<Self>) -> Self::Result {
+        Ok(self.presence.roster(msg.room.as_ref().map(|s| s.as_str())))
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<GetMetrics> for SockJSManager<S> {
+    type Result = Result<Metrics, ()>;
+
+    fn handle(&mut self, _: GetMetrics, _: &mut Context<Self>) -> Self::Result {
+        Ok(Arc::clone(&self.metrics))
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<BusMessage> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: BusMessage, _: &mut Context<Self>) {
+        if msg.origin() == self.id {
+            return
+        }
+
+        match msg {
+            BusMessage::Broadcast{msg, ..} => {
+                self.deliver_all(&Broadcast::new(Frame::Message(msg)));
+            }
+            BusMessage::Send{sid, msg, ..} => {
+                self.deliver(sid, &Broadcast::new(Frame::Message(msg)));
             }
-            if let Some(ref mut rec) = entry.record {
-                rec.add(Arc::clone(&msg.msg));
+            BusMessage::Publish{topic, data, ..} => {
+                self.deliver_publication(&Publication{topic, data});
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Mutex};
+
+    use super::*;
+    use store::MemoryStore;
+
+    #[derive(Default)]
+    struct Idle;
+
+    impl Actor for Idle {
+        type Context = SockJSContext<Self>;
+    }
+
+    impl Session for Idle {}
+
+    impl Handler<Message> for Idle {
+        type Result = ();
+
+        fn handle(&mut self, _: Message, _: &mut SockJSContext<Self>) {}
+    }
+
+    /// Session that sends message while transport is detached
+    #[derive(Default)]
+    struct Detached;
+
+    impl Actor for Detached {
+        type Context = SockJSContext<Self>;
+    }
+
+    impl Session for Detached {
+        fn released(&mut self, ctx: &mut SockJSContext<Self>) {
+            ctx.send("detached");
+        }
+    }
+
+    impl Handler<Message> for Detached {
+        type Result = ();
+
+        fn handle(&mut self, _: Message, _: &mut SockJSContext<Self>) {}
+    }
+
+    type Events = Arc<Mutex<Vec<SessionEvent>>>;
+
+    /// Records events and notifies test about them
+    struct Observer(Events, mpsc::Sender<()>);
+
+    impl Actor for Observer {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<SessionEvent> for Observer {
+        type Result = ();
+
+        fn handle(&mut self, ev: SessionEvent, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(ev);
+            let _ = self.1.send(());
+        }
+    }
+
+    /// Start manager and its observer in their own arbiters,
+    /// so test can block waiting for events
+    fn start<S, F>(factory: F) -> (Addr<Syn, SockJSManager<S>>, Events, mpsc::Receiver<()>)
+        where S: Session, F: FnOnce() -> SockJSManager<S> + Send + 'static
+    {
+        let events = Events::default();
+        let (tx, rx) = mpsc::channel();
+        let ev = Arc::clone(&events);
+        let observer: Addr<Syn, _> = Arbiter::start(move |_| Observer(ev, tx));
+        let sm = Arbiter::start(move |_| factory().subscribe(observer.recipient()));
+        (sm, events, rx)
+    }
+
+    /// Wait until observed events satisfy `done`
+    fn wait<F: Fn() -> bool>(rx: &mpsc::Receiver<()>, done: F) {
+        while !done() {
+            rx.recv_timeout(Duration::from_secs(5)).expect("event is not observed");
+        }
+    }
+
+    fn acquire<S: Session>(sys: &mut SystemRunner, sm: &Addr<Syn, SockJSManager<S>>, sid: &str)
+                           -> Record {
+        let (rec, _) = sys.run_until_complete(sm.send(Acquire::new(sid.to_owned(), "xhr")))
+            .unwrap().unwrap();
+        rec
+    }
+
+    fn release<S: Session>(sys: &mut SystemRunner, sm: &Addr<Syn, SockJSManager<S>>, rec: Record) {
+        sm.do_send(Release{ses: rec});
+        // round trip through manager mailbox
+        let _ = sys.run_until_complete(sm.send(Roster::all()));
+    }
+
+    fn closed(events: &Events, sid: &str) -> usize {
+        events.lock().unwrap().iter().filter(|ev| match **ev {
+            SessionEvent::Closed{sid: ref s, ref reason} =>
+                s == sid && *reason == CloseReason::Interrupted,
+            _ => false,
+        }).count()
+    }
+
+    fn record(window: usize) -> Record {
+        let (tx, _) = unbounded();
+        Record::new(Arc::new("sid".to_owned()), tx, window, Registry::new())
+    }
+
+    fn buffered(rec: &Record) -> Vec<String> {
+        rec.buffer.iter().map(|frm| match *frm.as_ref() {
+            Frame::Message(ref s) | Frame::MessageVec(ref s) => s.clone(),
+            ref frm => panic!("unexpected frame {:?}", frm),
+        }).collect()
+    }
+
+    #[test]
+    fn test_ack_retransmits() {
+        let mut rec = record(10);
+        rec.sent(Frame::Message("1".to_owned()));
+        rec.sent(Frame::MessageVec("[\"2\",\"3\"]".to_owned()));
+        rec.sent(Frame::Heartbeat);
+        rec.sent(Frame::Message("4".to_owned()));
+        // batch is one frame, heartbeats are not counted
+        assert_eq!(rec.seq, 3);
+
+        rec.ack(1);
+        assert_eq!(buffered(&rec), vec!["[\"2\",\"3\"]", "4"]);
+        assert_eq!(rec.seq, 1);
+
+        // retransmitted frames are numbered again
+        let frames: Vec<_> = rec.buffer.drain(..).collect();
+        for frm in frames {
+            rec.sent(frm);
+        }
+        assert_eq!(rec.seq, 3);
+        rec.ack(3);
+        assert!(rec.replay.is_empty());
+        assert!(rec.buffer.is_empty());
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut rec = record(2);
+        for i in 1..4 {
+            rec.sent(Frame::Message(i.to_string()));
+        }
+        // first frame is out of window
+        rec.ack(0);
+        assert_eq!(buffered(&rec), vec!["2", "3"]);
+
+        let mut rec = record(0);
+        rec.sent(Frame::Message("1".to_owned()));
+        assert_eq!(rec.seq, 0);
+        rec.ack(0);
+        assert!(rec.buffer.is_empty());
+    }
+
+    #[test]
+    fn test_resume_interrupted_session() {
+        let mut sys = System::new("test");
+        let (sm, events, _) = start(
+            || SockJSManager::<Idle>::default().resume_timeout(Duration::from_millis(500)));
+
+        let mut rec = acquire(&mut sys, &sm, "resumed");
+        rec.set_state(SessionState::Running);
+        rec.interrupted();
+        release(&mut sys, &sm, rec);
+
+        let rec = acquire(&mut sys, &sm, "resumed");
+        assert_eq!(rec.state, SessionState::Running);
+        release(&mut sys, &sm, rec);
+        assert_eq!(closed(&events, "resumed"), 0);
+    }
+
+    #[test]
+    fn test_interrupted_session_expires() {
+        let mut sys = System::new("test");
+        let store = MemoryStore::new();
+        let stored = store.clone();
+        let (sm, events, rx) = start(
+            move || SockJSManager::<Idle>::default()
+                .resume_timeout(Duration::from_millis(50))
+                .store(stored));
+
+        let mut rec = acquire(&mut sys, &sm, "expired");
+        rec.set_state(SessionState::Running);
+        rec.add(Frame::Message("pending".to_owned()));
+        rec.interrupted();
+        release(&mut sys, &sm, rec);
+        assert!(store.load("expired").unwrap().is_some());
+
+        wait(&rx, || closed(&events, "expired") > 0);
+        assert_eq!(closed(&events, "expired"), 1);
+        assert!(store.load("expired").unwrap().is_none());
+
+        // later requests get close frame, release does not re-arm resume timer
+        let rec = acquire(&mut sys, &sm, "expired");
+        assert_eq!(rec.state, SessionState::Closed);
+        release(&mut sys, &sm, rec);
+
+        // resume timer of other session fires after any timer armed by that release
+        let mut rec = acquire(&mut sys, &sm, "witness");
+        rec.set_state(SessionState::Running);
+        rec.interrupted();
+        release(&mut sys, &sm, rec);
+        wait(&rx, || closed(&events, "witness") > 0);
+
+        let rec = acquire(&mut sys, &sm, "expired");
+        assert_eq!(rec.state, SessionState::Closed);
+        release(&mut sys, &sm, rec);
+        assert_eq!(closed(&events, "expired"), 1);
+        assert!(store.load("expired").unwrap().is_none());
+    }
+
+    /// Observer that stops right away
+    struct Gone;
+
+    impl Actor for Gone {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Context<Self>) {
+            ctx.stop();
+        }
+    }
+
+    impl Handler<SessionEvent> for Gone {
+        type Result = ();
+
+        fn handle(&mut self, _: SessionEvent, _: &mut Context<Self>) {}
+    }
+
+    #[test]
+    fn test_notify_drops_stopped_observers() {
+        let mut sys = System::new("test");
+        let events = Events::default();
+        let (tx, _) = mpsc::channel();
+        let live: Addr<Syn, _> = Observer(Arc::clone(&events), tx).start();
+        let gone: Addr<Syn, _> = Gone.start();
+        let mut sm = SockJSManager::<Idle>::default()
+            .subscribe(live.clone().recipient())
+            .subscribe(gone.recipient());
+
+        // let stopped observer drop its mailbox
+        let _ = sys.run_until_complete(live.send(SessionEvent::Expired{sid: "a".to_owned()}));
+        sm.notify(SessionEvent::Expired{sid: "b".to_owned()});
+        assert_eq!(sm.observers.len(), 1);
+
+        sm.notify(SessionEvent::Expired{sid: "c".to_owned()});
+        let _ = sys.run_until_complete(live.send(SessionEvent::Expired{sid: "d".to_owned()}));
+        let sids: Vec<_> = events.lock().unwrap().iter().map(|ev| match *ev {
+            SessionEvent::Expired{ref sid} => sid.clone(),
+            ref ev => panic!("unexpected event {:?}", ev),
+        }).collect();
+        assert_eq!(sids, vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_restore_detached_frames() {
+        let mut sys = System::new("test");
+        let store = MemoryStore::new();
+        let stored = store.clone();
+        let (sm, _, _) = start(move || SockJSManager::<Detached>::default().store(stored));
+        let mut rec = acquire(&mut sys, &sm, "detached");
+        rec.set_state(SessionState::Running);
+        release(&mut sys, &sm, rec);
+
+        // context reports buffered frame to the manager
+        let stored = |store: &MemoryStore| store.load("detached").unwrap()
+            .map(|session| session.frames.len()).unwrap_or(0);
+        for _ in 0..100 {
+            if stored(&store) > 0 {
+                break
+            }
+            let _ = sys.run_until_complete(sm.send(Roster::all()));
+        }
+        assert_eq!(stored(&store), 1);
+
+        // restarted manager resumes session from the store
+        let stored = store.clone();
+        let (sm, _, _) = start(move || SockJSManager::<Detached>::default().store(stored));
+        let rec = acquire(&mut sys, &sm, "detached");
+        assert_eq!(rec.state, SessionState::Running);
+        assert_eq!(buffered(&rec), vec!["detached"]);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_buffer_depth_of_detached_session() {
+        use futures::Stream;
+
+        let mut sys = System::new("test");
+        let (sm, _, _) = start(SockJSManager::<Detached>::default);
+        let mut rec = acquire(&mut sys, &sm, "detached");
+        rec.set_state(SessionState::Running);
+        release(&mut sys, &sm, rec);
+
+        // context flushes its queue before transport gets ready
+        let (_, rx) = sys.run_until_complete(
+            sm.send(Acquire::new("detached".to_owned(), "xhr"))).unwrap().unwrap();
+        let flushed: Vec<_> = sys.run_until_complete(
+            rx.take_while(|item| Ok(match *item {
+                ChannelItem::Ready => false,
+                _ => true,
+            })).collect()).unwrap();
+        assert_eq!(flushed.len(), 1);
+
+        let reg = sys.run_until_complete(sm.send(GetMetrics)).unwrap().unwrap();
+        let s = ::metrics::render(&[reg]);
+        assert!(s.contains("sockjs_buffer_depth_sum 1\n"), "{}", s);
+        assert!(s.contains("sockjs_buffer_depth_count 2\n"), "{}", s);
+    }
+
+    #[test]
+    fn test_released_unknown_session() {
+        let mut sys = System::new("test");
+        let (sm, events, rx) = start(SockJSManager::<Idle>::default);
+        let released = || events.lock().unwrap().iter().filter(|ev| match **ev {
+            SessionEvent::TransportReleased{..} => true,
+            _ => false,
+        }).count();
+
+        release(&mut sys, &sm, record(0));
+
+        // observer gets events in order, unknown session would be reported first
+        let rec = acquire(&mut sys, &sm, "known");
+        release(&mut sys, &sm, rec);
+        wait(&rx, || released() > 0);
+        assert_eq!(released(), 1);
+    }
+}
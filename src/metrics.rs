@@ -0,0 +1,332 @@
+//! Prometheus style metrics
+//!
+//! Metrics are collected only if `metrics` feature is enabled,
+//! otherwise all functions are no-op.
+//!
+//! Every session manager keeps its own registry, application renders
+//! sum of registries of its managers.
+#[cfg(feature = "metrics")]
+pub use self::registry::*;
+#[cfg(not(feature = "metrics"))]
+pub use self::noop::*;
+
+#[cfg(feature = "metrics")]
+mod registry {
+    use std::fmt::Write;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use protocol::Frame;
+    use session::{CloseReason, SessionState};
+
+    const BUCKETS: [usize; 9] = [0, 1, 2, 5, 10, 20, 50, 100, 1000];
+
+    const STATES: [&str; 4] = ["new", "running", "interrupted", "closed"];
+
+    const REASONS: [&str; 6] =
+        ["normal", "expired", "interrupted", "peer", "protocol_error", "kicked"];
+
+    const TRANSPORTS: [&str; 9] = [
+        "eventsource", "htmlfile", "jsonp", "jsonp_send", "rawwebsocket",
+        "websocket", "xhr", "xhr_send", "xhr_streaming"];
+
+    fn state_idx(state: &SessionState) -> usize {
+        match *state {
+            SessionState::New => 0,
+            SessionState::Running => 1,
+            SessionState::Interrupted => 2,
+            SessionState::Closed => 3,
+        }
+    }
+
+    fn reason_idx(reason: &CloseReason) -> usize {
+        match *reason {
+            CloseReason::Normal => 0,
+            CloseReason::Expired => 1,
+            CloseReason::Interrupted => 2,
+            CloseReason::Peer { .. } => 3,
+            CloseReason::ProtocolError(_) => 4,
+            CloseReason::Kicked(_) => 5,
+        }
+    }
+
+    fn transport_idx(transport: &str) -> Option<usize> {
+        TRANSPORTS.iter().position(|t| *t == transport)
+    }
+
+    /// Payload size of the frame
+    fn frame_size(frm: &Frame) -> usize {
+        match *frm {
+            Frame::Message(ref s) | Frame::MessageVec(ref s) => s.len(),
+            Frame::MessageBlob(ref b) => b.len(),
+            Frame::Close(ref code) => code.reason().len(),
+            Frame::Open | Frame::Heartbeat => 0,
+        }
+    }
+
+    fn get(val: &AtomicUsize) -> usize {
+        val.load(Ordering::Relaxed)
+    }
+
+    fn add(val: &AtomicUsize, n: usize) {
+        val.fetch_add(n, Ordering::Relaxed);
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        frames: AtomicUsize,
+        bytes: AtomicUsize,
+    }
+
+    /// Metrics of one session manager
+    #[derive(Default)]
+    pub struct Registry {
+        by_state: [AtomicUsize; 4],
+        by_transport: [AtomicUsize; 9],
+        opened: AtomicUsize,
+        closed: [AtomicUsize; 6],
+        sent: [Counter; 9],
+        received: [Counter; 9],
+        depth: [AtomicUsize; 9],
+        depth_sum: AtomicUsize,
+        depth_count: AtomicUsize,
+    }
+
+    /// Shared handle to manager's registry
+    pub type Metrics = Arc<Registry>;
+
+    impl Registry {
+        pub fn new() -> Metrics {
+            Arc::new(Registry::default())
+        }
+
+        /// Set live sessions gauges
+        pub fn sessions<'a, I>(&self, sessions: I)
+            where I: Iterator<Item=(&'a SessionState, Option<&'static str>)>
+        {
+            let mut by_state = [0; 4];
+            let mut by_transport = [0; 9];
+            for (state, transport) in sessions {
+                by_state[state_idx(state)] += 1;
+                if let Some(idx) = transport.and_then(transport_idx) {
+                    by_transport[idx] += 1;
+                }
+            }
+            for (gauge, val) in self.by_state.iter().zip(by_state.iter()) {
+                gauge.store(*val, Ordering::Relaxed);
+            }
+            for (gauge, val) in self.by_transport.iter().zip(by_transport.iter()) {
+                gauge.store(*val, Ordering::Relaxed);
+            }
+        }
+
+        /// New session get opened
+        pub fn session_opened(&self) {
+            add(&self.opened, 1);
+        }
+
+        /// Session get closed
+        pub fn session_closed(&self, reason: &CloseReason) {
+            add(&self.closed[reason_idx(reason)], 1);
+        }
+
+        /// Frame get sent to the peer
+        pub fn frame_sent(&self, transport: &'static str, frm: &Frame) {
+            if let Some(idx) = transport_idx(transport) {
+                add(&self.sent[idx].frames, 1);
+                add(&self.sent[idx].bytes, frame_size(frm));
+            }
+        }
+
+        /// Messages received from the peer
+        pub fn frames_received(&self, transport: &'static str, frames: usize, bytes: usize) {
+            if let Some(idx) = transport_idx(transport) {
+                add(&self.received[idx].frames, frames);
+                add(&self.received[idx].bytes, bytes);
+            }
+        }
+
+        /// Depth of session buffer
+        pub fn buffer_depth(&self, depth: usize) {
+            for (idx, bound) in BUCKETS.iter().enumerate() {
+                if depth <= *bound {
+                    add(&self.depth[idx], 1);
+                }
+            }
+            add(&self.depth_sum, depth);
+            add(&self.depth_count, 1);
+        }
+    }
+
+    fn sum<F>(registries: &[Metrics], f: F) -> usize where F: Fn(&Registry) -> &AtomicUsize {
+        registries.iter().map(|reg| get(f(reg))).sum()
+    }
+
+    fn counters(reg: &Registry, received: bool) -> &[Counter; 9] {
+        if received { &reg.received } else { &reg.sent }
+    }
+
+    /// Render sum of registries in prometheus text exposition format
+    pub fn render(regs: &[Metrics]) -> String {
+        let mut s = String::new();
+
+        let _ = writeln!(s, "# HELP sockjs_sessions Live sessions by state");
+        let _ = writeln!(s, "# TYPE sockjs_sessions gauge");
+        for (idx, state) in STATES.iter().enumerate() {
+            let _ = writeln!(s, "sockjs_sessions{{state=\"{}\"}} {}",
+                             state, sum(regs, |reg| &reg.by_state[idx]));
+        }
+
+        let _ = writeln!(s, "# HELP sockjs_transport_sessions Sessions acquired by transport");
+        let _ = writeln!(s, "# TYPE sockjs_transport_sessions gauge");
+        for (idx, transport) in TRANSPORTS.iter().enumerate() {
+            let _ = writeln!(s, "sockjs_transport_sessions{{transport=\"{}\"}} {}",
+                             transport, sum(regs, |reg| &reg.by_transport[idx]));
+        }
+
+        let _ = writeln!(s, "# HELP sockjs_sessions_opened_total Opened sessions");
+        let _ = writeln!(s, "# TYPE sockjs_sessions_opened_total counter");
+        let _ = writeln!(s, "sockjs_sessions_opened_total {}", sum(regs, |reg| &reg.opened));
+
+        let _ = writeln!(s, "# HELP sockjs_sessions_closed_total Closed sessions by reason");
+        let _ = writeln!(s, "# TYPE sockjs_sessions_closed_total counter");
+        for (idx, reason) in REASONS.iter().enumerate() {
+            let _ = writeln!(s, "sockjs_sessions_closed_total{{reason=\"{}\"}} {}",
+                             reason, sum(regs, |reg| &reg.closed[idx]));
+        }
+
+        for &(name, dir, received) in &[("sent", "to", false), ("received", "from", true)] {
+            let _ = writeln!(s, "# HELP sockjs_frames_{}_total Frames {} peers", name, dir);
+            let _ = writeln!(s, "# TYPE sockjs_frames_{}_total counter", name);
+            for (idx, transport) in TRANSPORTS.iter().enumerate() {
+                let _ = writeln!(s, "sockjs_frames_{}_total{{transport=\"{}\"}} {}", name, transport,
+                                 sum(regs, |reg| &counters(reg, received)[idx].frames));
+            }
+            let _ = writeln!(s, "# HELP sockjs_bytes_{}_total Payload bytes {} peers", name, dir);
+            let _ = writeln!(s, "# TYPE sockjs_bytes_{}_total counter", name);
+            for (idx, transport) in TRANSPORTS.iter().enumerate() {
+                let _ = writeln!(s, "sockjs_bytes_{}_total{{transport=\"{}\"}} {}", name, transport,
+                                 sum(regs, |reg| &counters(reg, received)[idx].bytes));
+            }
+        }
+
+        let _ = writeln!(s, "# HELP sockjs_buffer_depth Buffered frames on transport acquire");
+        let _ = writeln!(s, "# TYPE sockjs_buffer_depth histogram");
+        for (idx, bound) in BUCKETS.iter().enumerate() {
+            let _ = writeln!(s, "sockjs_buffer_depth_bucket{{le=\"{}\"}} {}",
+                             bound, sum(regs, |reg| &reg.depth[idx]));
+        }
+        let count = sum(regs, |reg| &reg.depth_count);
+        let _ = writeln!(s, "sockjs_buffer_depth_bucket{{le=\"+Inf\"}} {}", count);
+        let _ = writeln!(s, "sockjs_buffer_depth_sum {}", sum(regs, |reg| &reg.depth_sum));
+        let _ = writeln!(s, "sockjs_buffer_depth_count {}", count);
+
+        s
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use protocol::CloseCode;
+
+        fn line<'a>(s: &'a str, metric: &str) -> &'a str {
+            s.lines().find(|l| l.starts_with(metric) && l[metric.len()..].starts_with(' '))
+                .unwrap_or_else(|| panic!("{} is not rendered", metric))
+        }
+
+        #[test]
+        fn test_render_sums_registries() {
+            let regs = vec![Registry::new(), Registry::new()];
+            regs[0].session_opened();
+            regs[1].session_opened();
+            regs[1].session_closed(&CloseReason::Expired);
+            regs[0].frame_sent("xhr", &Frame::Message("hello".to_owned()));
+            regs[1].frame_sent("xhr", &Frame::Close(CloseCode::GoAway));
+            regs[1].frames_received("xhr_send", 3, 12);
+
+            let s = render(&regs);
+            assert_eq!(line(&s, "sockjs_sessions_opened_total"), "sockjs_sessions_opened_total 2");
+            assert_eq!(line(&s, "sockjs_sessions_closed_total{reason=\"expired\"}"),
+                       "sockjs_sessions_closed_total{reason=\"expired\"} 1");
+            assert_eq!(line(&s, "sockjs_frames_sent_total{transport=\"xhr\"}"),
+                       "sockjs_frames_sent_total{transport=\"xhr\"} 2");
+            assert_eq!(line(&s, "sockjs_bytes_sent_total{transport=\"xhr\"}"),
+                       format!("sockjs_bytes_sent_total{{transport=\"xhr\"}} {}",
+                               5 + CloseCode::GoAway.reason().len()));
+            assert_eq!(line(&s, "sockjs_frames_received_total{transport=\"xhr_send\"}"),
+                       "sockjs_frames_received_total{transport=\"xhr_send\"} 3");
+        }
+
+        #[test]
+        fn test_gauges_are_per_registry() {
+            let regs = vec![Registry::new(), Registry::new()];
+            let running = SessionState::Running;
+            let interrupted = SessionState::Interrupted;
+            regs[0].sessions(vec![(&running, Some("websocket")), (&running, None)].into_iter());
+            regs[1].sessions(vec![(&interrupted, None)].into_iter());
+            // second report replaces only its own registry values
+            regs[1].sessions(vec![(&running, Some("xhr_streaming"))].into_iter());
+
+            let s = render(&regs);
+            assert_eq!(line(&s, "sockjs_sessions{state=\"running\"}"),
+                       "sockjs_sessions{state=\"running\"} 3");
+            assert_eq!(line(&s, "sockjs_sessions{state=\"interrupted\"}"),
+                       "sockjs_sessions{state=\"interrupted\"} 0");
+            assert_eq!(line(&s, "sockjs_transport_sessions{transport=\"websocket\"}"),
+                       "sockjs_transport_sessions{transport=\"websocket\"} 1");
+        }
+
+        #[test]
+        fn test_buffer_depth_histogram() {
+            let regs = vec![Registry::new()];
+            regs[0].buffer_depth(0);
+            regs[0].buffer_depth(7);
+            regs[0].buffer_depth(5000);
+
+            let s = render(&regs);
+            assert_eq!(line(&s, "sockjs_buffer_depth_bucket{le=\"0\"}"),
+                       "sockjs_buffer_depth_bucket{le=\"0\"} 1");
+            assert_eq!(line(&s, "sockjs_buffer_depth_bucket{le=\"10\"}"),
+                       "sockjs_buffer_depth_bucket{le=\"10\"} 2");
+            assert_eq!(line(&s, "sockjs_buffer_depth_bucket{le=\"+Inf\"}"),
+                       "sockjs_buffer_depth_bucket{le=\"+Inf\"} 3");
+            assert_eq!(line(&s, "sockjs_buffer_depth_sum"), "sockjs_buffer_depth_sum 5007");
+        }
+
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod noop {
+    use std::sync::Arc;
+
+    use protocol::Frame;
+    use session::{CloseReason, SessionState};
+
+    pub struct Registry;
+
+    pub type Metrics = Arc<Registry>;
+
+    impl Registry {
+        pub fn new() -> Metrics {
+            Arc::new(Registry)
+        }
+
+        pub fn sessions<'a, I>(&self, _: I)
+            where I: Iterator<Item=(&'a SessionState, Option<&'static str>)> {}
+
+        pub fn session_opened(&self) {}
+
+        pub fn session_closed(&self, _: &CloseReason) {}
+
+        pub fn frame_sent(&self, _: &'static str, _: &Frame) {}
+
+        pub fn frames_received(&self, _: &'static str, _: usize, _: usize) {}
+
+        pub fn buffer_depth(&self, _: usize) {}
+    }
+
+    pub fn render(_: &[Metrics]) -> String {
+        String::new()
+    }
+}
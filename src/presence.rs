@@ -0,0 +1,273 @@
+//! Presence tracking
+//!
+//! Session attaches user id with `SockJSContext::track()`, user can have
+//! several sessions (e.g. browser tabs). Manager emits `SessionEvent::Joined`
+//! when first session of the user arrives and `SessionEvent::Left` when last
+//! one leaves, same for rooms.
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+
+use actix::Message as ActixMessage;
+use serde_json::Value;
+
+use manager::SessionEvent;
+
+/// Attach user id and metadata to session
+#[derive(Debug, Message)]
+pub struct Track {
+    pub sid: Arc<String>,
+    pub user: String,
+    pub meta: Value,
+}
+
+/// Join session to the room
+#[derive(Debug, Message)]
+pub struct Join {
+    pub sid: Arc<String>,
+    pub room: String,
+}
+
+/// Remove session from the room
+#[derive(Debug, Message)]
+pub struct Leave {
+    pub sid: Arc<String>,
+    pub room: String,
+}
+
+/// Query current roster, all online users or users in specific room
+#[derive(Debug)]
+pub struct Roster {
+    pub room: Option<String>,
+}
+
+impl Roster {
+    /// All online users
+    pub fn all() -> Roster {
+        Roster{room: None}
+    }
+
+    /// Users in the room
+    pub fn room<T: Into<String>>(room: T) -> Roster {
+        Roster{room: Some(room.into())}
+    }
+}
+
+impl ActixMessage for Roster {
+    type Result = Result<Vec<PresenceUser>, ()>;
+}
+
+/// Online user
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PresenceUser {
+    /// User id
+    pub user: String,
+    /// Metadata of the latest tracked session
+    pub meta: Value,
+    /// Number of user's sessions, in the room if roster is requested for room
+    pub sessions: usize,
+}
+
+struct User {
+    meta: Value,
+    sessions: HashSet<Arc<String>>,
+    rooms: HashMap<String, usize>,
+}
+
+struct Tracked {
+    user: String,
+    rooms: HashSet<String>,
+}
+
+/// User -> sessions map
+#[derive(Default)]
+pub(crate) struct Presence {
+    users: HashMap<String, User>,
+    sessions: HashMap<Arc<String>, Tracked>,
+}
+
+impl Presence {
+    pub fn track(&mut self, sid: Arc<String>, user: String, meta: Value) -> Vec<SessionEvent> {
+        // session switches user
+        let mut events = match self.sessions.get(&sid).map(|t| t.user == user) {
+            Some(false) => self.untrack(&sid),
+            _ => Vec::new(),
+        };
+
+        let joined = !self.users.contains_key(&user);
+        {
+            let entry = self.users.entry(user.clone()).or_insert_with(|| User {
+                meta: Value::Null,
+                sessions: HashSet::new(),
+                rooms: HashMap::new(),
+            });
+            entry.meta = meta.clone();
+            entry.sessions.insert(Arc::clone(&sid));
+        }
+        if joined {
+            events.push(SessionEvent::Joined{user: user.clone(), room: None, meta});
+        }
+        self.sessions.entry(sid).or_insert_with(|| Tracked{user, rooms: HashSet::new()});
+        events
+    }
+
+    pub fn join(&mut self, sid: &Arc<String>, room: String) -> Option<SessionEvent> {
+        let tracked = match self.sessions.get_mut(sid) {
+            Some(tracked) => tracked,
+            None => {
+                warn!("Session {} is not tracked, can not join room {}", sid, room);
+                return None
+            }
+        };
+        if !tracked.rooms.insert(room.clone()) {
+            return None
+        }
+        let user = self.users.get_mut(&tracked.user)?;
+        let count = user.rooms.entry(room.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            Some(SessionEvent::Joined{
+                user: tracked.user.clone(), room: Some(room), meta: user.meta.clone()})
+        } else {
+            None
+        }
+    }
+
+    pub fn leave(&mut self, sid: &Arc<String>, room: &str) -> Option<SessionEvent> {
+        let tracked = self.sessions.get_mut(sid)?;
+        if !tracked.rooms.remove(room) {
+            return None
+        }
+        let user = self.users.get_mut(&tracked.user)?;
+        let left = match user.rooms.get_mut(room) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if left {
+            user.rooms.remove(room);
+            Some(SessionEvent::Left{user: tracked.user.clone(), room: Some(room.to_owned())})
+        } else {
+            None
+        }
+    }
+
+    /// Session is gone, remove it from rooms and from user's sessions
+    pub fn untrack(&mut self, sid: &Arc<String>) -> Vec<SessionEvent> {
+        let mut events = Vec::new();
+        let rooms: Vec<_> = match self.sessions.get(sid) {
+            Some(tracked) => tracked.rooms.iter().cloned().collect(),
+            None => return events,
+        };
+        for room in rooms {
+            events.extend(self.leave(sid, &room));
+        }
+
+        if let Some(tracked) = self.sessions.remove(sid) {
+            let left = match self.users.get_mut(&tracked.user) {
+                Some(user) => {
+                    user.sessions.remove(sid);
+                    user.sessions.is_empty()
+                }
+                None => false,
+            };
+            if left {
+                self.users.remove(&tracked.user);
+                events.push(SessionEvent::Left{user: tracked.user, room: None});
+            }
+        }
+        events
+    }
+
+    /// User of tracked session
+    pub fn user(&self, sid: &Arc<String>) -> Option<&str> {
+        self.sessions.get(sid).map(|t| t.user.as_str())
+    }
+
+    pub fn roster(&self, room: Option<&str>) -> Vec<PresenceUser> {
+        self.users.iter()
+            .filter_map(|(id, user)| {
+                let sessions = match room {
+                    Some(room) => user.rooms.get(room).cloned().unwrap_or(0),
+                    None => user.sessions.len(),
+                };
+                if sessions == 0 {
+                    None
+                } else {
+                    Some(PresenceUser{user: id.clone(), meta: user.meta.clone(), sessions})
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sid(s: &str) -> Arc<String> {
+        Arc::new(s.to_owned())
+    }
+
+    fn names<I: IntoIterator<Item=SessionEvent>>(events: I) -> Vec<String> {
+        events.into_iter().map(|ev| match ev {
+            SessionEvent::Joined{user, room: None, ..} => format!("joined {}", user),
+            SessionEvent::Joined{user, room: Some(room), ..} => format!("joined {} {}", user, room),
+            SessionEvent::Left{user, room: None} => format!("left {}", user),
+            SessionEvent::Left{user, room: Some(room)} => format!("left {} {}", user, room),
+            ev => panic!("unexpected event {:?}", ev),
+        }).collect()
+    }
+
+    #[test]
+    fn test_track_several_sessions() {
+        let mut presence = Presence::default();
+        let (s1, s2) = (sid("s1"), sid("s2"));
+
+        assert_eq!(names(presence.track(Arc::clone(&s1), "bob".to_owned(), Value::from(1))),
+                   vec!["joined bob"]);
+        assert!(presence.track(Arc::clone(&s2), "bob".to_owned(), Value::from(2)).is_empty());
+        assert_eq!(presence.roster(None),
+                   vec![PresenceUser{user: "bob".to_owned(), meta: Value::from(2), sessions: 2}]);
+
+        assert!(presence.untrack(&s1).is_empty());
+        assert_eq!(names(presence.untrack(&s2)), vec!["left bob"]);
+        assert!(presence.untrack(&s2).is_empty());
+        assert!(presence.roster(None).is_empty());
+    }
+
+    #[test]
+    fn test_rooms() {
+        let mut presence = Presence::default();
+        let (s1, s2) = (sid("s1"), sid("s2"));
+        assert!(presence.join(&s1, "lobby".to_owned()).is_none());
+
+        presence.track(Arc::clone(&s1), "bob".to_owned(), Value::Null);
+        presence.track(Arc::clone(&s2), "bob".to_owned(), Value::Null);
+        assert_eq!(names(presence.join(&s1, "lobby".to_owned())), vec!["joined bob lobby"]);
+        assert!(presence.join(&s1, "lobby".to_owned()).is_none());
+        assert!(presence.join(&s2, "lobby".to_owned()).is_none());
+        assert_eq!(presence.roster(Some("lobby"))[0].sessions, 2);
+        assert!(presence.roster(Some("kitchen")).is_empty());
+
+        assert!(presence.leave(&s1, "lobby").is_none());
+        assert!(presence.leave(&s1, "lobby").is_none());
+        assert!(presence.untrack(&s1).is_empty());
+        assert_eq!(names(presence.untrack(&s2)), vec!["left bob lobby", "left bob"]);
+        assert!(presence.roster(Some("lobby")).is_empty());
+    }
+
+    #[test]
+    fn test_switch_user() {
+        let mut presence = Presence::default();
+        let s1 = sid("s1");
+        presence.track(Arc::clone(&s1), "bob".to_owned(), Value::Null);
+        presence.join(&s1, "lobby".to_owned());
+
+        assert_eq!(names(presence.track(Arc::clone(&s1), "alice".to_owned(), Value::Null)),
+                   vec!["left bob lobby", "left bob", "joined alice"]);
+        assert_eq!(presence.roster(None)[0].user, "alice");
+        assert!(presence.roster(Some("lobby")).is_empty());
+    }
+}
@@ -1,7 +1,9 @@
 use bytes::Bytes;
+#[cfg(feature = "client")]
+use serde_json;
 use session::SessionError;
 
-#[derive(Debug, Message)]
+#[derive(Clone, Debug, Message)]
 pub enum Frame {
     Open,
     Close(CloseCode),
@@ -26,6 +28,28 @@ impl Frame {
     }
 }
 
+#[cfg(feature = "client")]
+impl Frame {
+    /// Decode frame sent by server, array frame is decoded
+    /// into message per array item
+    pub(crate) fn decode(s: &str) -> Result<Vec<Frame>, String> {
+        let kind = s.chars().next();
+        let payload = &s[kind.map(|c| c.len_utf8()).unwrap_or(0)..];
+        match kind {
+            Some('o') if payload.is_empty() => Ok(vec![Frame::Open]),
+            // streaming transports send `hhh..h` prelude
+            Some('h') if payload.chars().all(|c| c == 'h') => Ok(vec![Frame::Heartbeat]),
+            Some('a') => serde_json::from_str::<Vec<String>>(payload)
+                .map(|msgs| msgs.into_iter().map(Frame::Message).collect())
+                .map_err(|e| format!("Broken message frame: {}", e)),
+            Some('c') => serde_json::from_str::<(u16, String)>(payload)
+                .map(|(code, reason)| vec![Frame::Close(CloseCode::from_parts(code, reason))])
+                .map_err(|e| format!("Broken close frame: {}", e)),
+            _ => Err(format!("Unknown frame: {:?}", s)),
+        }
+    }
+}
+
 impl From<String> for Frame {
     fn from(s: String) -> Frame {
         Frame::Message(s)
@@ -38,29 +62,57 @@ impl From<Bytes> for Frame {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Close code and reason sent to the peer in `c[code,"reason"]` frame
+#[derive(Clone, Debug, PartialEq)]
 pub enum CloseCode {
     Interrupted,
     GoAway,
     Acquired,
     InternalError,
+    /// Application defined close code and reason
+    Custom(u16, String),
 }
 
 impl CloseCode {
+    /// Create application defined close code.
+    ///
+    /// Returns `None` if code is not in `3000-4999` range.
+    pub fn custom<R: Into<String>>(code: u16, reason: R) -> Option<CloseCode> {
+        if code >= 3000 && code <= 4999 {
+            Some(CloseCode::Custom(code, reason.into()))
+        } else {
+            None
+        }
+    }
+
+    /// Close code received from server
+    #[cfg(feature = "client")]
+    pub(crate) fn from_parts(code: u16, reason: String) -> CloseCode {
+        match code {
+            1002 => CloseCode::Interrupted,
+            2010 => CloseCode::Acquired,
+            3000 if reason == CloseCode::GoAway.reason() => CloseCode::GoAway,
+            3000 if reason == CloseCode::InternalError.reason() => CloseCode::InternalError,
+            _ => CloseCode::Custom(code, reason),
+        }
+    }
+
     pub fn num(&self) -> usize {
         match *self {
             CloseCode::Interrupted => 1002,
             CloseCode::Acquired => 2010,
             CloseCode::GoAway | CloseCode::InternalError => 3000,
+            CloseCode::Custom(code, _) => code as usize,
         }
     }
 
-    pub fn reason(&self) -> &'static str {
+    pub fn reason(&self) -> &str {
         match *self {
             CloseCode::Interrupted => "Connection interrupted",
             CloseCode::GoAway => "Go away!",
             CloseCode::Acquired => "Another connection still open",
             CloseCode::InternalError => "Internal error",
+            CloseCode::Custom(_, ref reason) => reason,
         }
     }
 }
@@ -0,0 +1,272 @@
+//! JSON-RPC 2.0 over sockjs messages
+//!
+//! Session registers method handlers in `Rpc` registry and passes peer
+//! messages to `Rpc::handle()`, which answers requests and resolves
+//! responses to calls made with `SockJSContext::call()`. Messages that
+//! are not JSON-RPC 2.0 objects are returned back to the session.
+//!
+//! ```rust,ignore
+//! struct Calc {
+//!     rpc: Rc<Rpc<Calc>>,
+//! }
+//!
+//! impl Default for Calc {
+//!     fn default() -> Calc {
+//!         Calc{rpc: Rc::new(Rpc::new()
+//!             .method("add", |_, (a, b): (i64, i64), _| Ok(a + b)))}
+//!     }
+//! }
+//!
+//! impl Handler<Message> for Calc {
+//!     type Result = ();
+//!
+//!     fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+//!         let rpc = Rc::clone(&self.rpc);
+//!         if let Some(msg) = rpc.handle(self, msg, ctx) {
+//!             // not rpc message
+//!         }
+//!     }
+//! }
+//! ```
+use std::fmt;
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Value};
+
+use context::SockJSContext;
+use session::{Message, Session};
+
+/// JSON-RPC error object
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new<T: Into<String>>(code: i64, message: T) -> RpcError {
+        RpcError{code, message: message.into(), data: None}
+    }
+
+    /// Set additional error data
+    pub fn data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn parse_error() -> RpcError {
+        RpcError::new(-32700, "Parse error")
+    }
+
+    pub fn invalid_request() -> RpcError {
+        RpcError::new(-32600, "Invalid Request")
+    }
+
+    pub fn method_not_found() -> RpcError {
+        RpcError::new(-32601, "Method not found")
+    }
+
+    pub fn invalid_params<T: Into<String>>(message: T) -> RpcError {
+        RpcError::new(-32602, message)
+    }
+
+    pub fn internal_error<T: Into<String>>(message: T) -> RpcError {
+        RpcError::new(-32603, message)
+    }
+
+    /// Call made with `SockJSContext::call()` was not answered in time
+    pub fn timeout() -> RpcError {
+        RpcError::new(-32000, "Request timed out")
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+type Method<A> = Box<Fn(&mut A, Value, &mut SockJSContext<A>) -> Result<Value, RpcError>>;
+
+/// Registry of methods peer can call
+pub struct Rpc<A: Session> {
+    methods: HashMap<String, Method<A>>,
+}
+
+impl<A: Session> Default for Rpc<A> {
+    fn default() -> Rpc<A> {
+        Rpc{methods: HashMap::new()}
+    }
+}
+
+impl<A: Session> Rpc<A> {
+    pub fn new() -> Rpc<A> {
+        Rpc::default()
+    }
+
+    /// Register method handler
+    ///
+    /// Params get deserialized into `P`, params that can not be deserialized
+    /// are reported to peer as invalid params error. Missing params
+    /// are deserialized from `null`.
+    pub fn method<F, P, R>(mut self, name: &str, f: F) -> Self
+        where F: Fn(&mut A, P, &mut SockJSContext<A>) -> Result<R, RpcError> + 'static,
+              P: DeserializeOwned,
+              R: Serialize,
+    {
+        self.methods.insert(name.to_owned(), Box::new(move |act, params, ctx| {
+            let params = serde_json::from_value(params)
+                .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+            let result = f(act, params, ctx)?;
+            serde_json::to_value(result).map_err(|e| RpcError::internal_error(e.to_string()))
+        }));
+        self
+    }
+
+    /// Handle peer message
+    ///
+    /// Requests get answered, responses resolve pending calls.
+    /// Returns message back if it is not JSON-RPC 2.0 message.
+    pub fn handle(&self, act: &mut A, msg: Message, ctx: &mut SockJSContext<A>)
+                  -> Option<Message>
+    {
+        let value: Value = match serde_json::from_str(&msg.0) {
+            Ok(value) => value,
+            Err(_) => return Some(msg),
+        };
+        match value {
+            Value::Object(obj) => {
+                if !is_rpc(&obj) {
+                    return Some(msg)
+                }
+                if let Some(resp) = self.dispatch(act, obj, ctx) {
+                    ctx.send(resp.to_string());
+                }
+            }
+            Value::Array(batch) => {
+                let rpc = batch.iter().all(|v| match *v {
+                    Value::Object(ref obj) => is_rpc(obj),
+                    _ => false,
+                });
+                if batch.is_empty() || !rpc {
+                    return Some(msg)
+                }
+                let responses: Vec<_> = batch.into_iter()
+                    .filter_map(|v| match v {
+                        Value::Object(obj) => self.dispatch(act, obj, ctx),
+                        _ => None,
+                    })
+                    .collect();
+                if !responses.is_empty() {
+                    ctx.send(Value::Array(responses).to_string());
+                }
+            }
+            _ => return Some(msg),
+        }
+        None
+    }
+
+    /// Handle single request or response, returns response for the peer
+    fn dispatch(&self, act: &mut A, mut obj: Map<String, Value>, ctx: &mut SockJSContext<A>)
+                -> Option<Value>
+    {
+        let id = obj.remove("id");
+
+        let method = match obj.remove("method") {
+            Some(Value::String(method)) => method,
+            Some(_) => return Some(response(id.unwrap_or(Value::Null),
+                                            Err(RpcError::invalid_request()))),
+            None => {
+                // response to the call made by server
+                let result = match (obj.remove("result"), obj.remove("error")) {
+                    (Some(result), None) => Ok(result),
+                    (None, Some(err)) => Err(serde_json::from_value(err)
+                                             .unwrap_or_else(|_| RpcError::invalid_request())),
+                    _ => return Some(response(id.unwrap_or(Value::Null),
+                                              Err(RpcError::invalid_request()))),
+                };
+                match id.as_ref().and_then(|id| id.as_u64()) {
+                    Some(id) => ctx.resolve_call(id, result),
+                    None => warn!("Response to unknown call: {:?}", id),
+                }
+                return None
+            }
+        };
+
+        let params = obj.remove("params").unwrap_or(Value::Null);
+        let result = match self.methods.get(&method) {
+            Some(f) => f(act, params, ctx),
+            None => Err(RpcError::method_not_found()),
+        };
+        // notifications are not answered
+        id.map(|id| response(id, result))
+    }
+}
+
+fn is_rpc(obj: &Map<String, Value>) -> bool {
+    obj.get("jsonrpc").and_then(|v| v.as_str()) == Some("2.0")
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    let mut obj = Map::new();
+    obj.insert("jsonrpc".to_owned(), Value::String("2.0".to_owned()));
+    match result {
+        Ok(result) => {
+            obj.insert("result".to_owned(), result);
+        }
+        Err(err) => {
+            obj.insert("error".to_owned(), serde_json::to_value(err).unwrap());
+        }
+    }
+    obj.insert("id".to_owned(), id);
+    Value::Object(obj)
+}
+
+/// Request sent to the peer, `null` params are omitted
+pub(crate) fn request(id: u64, method: &str, params: Value) -> String {
+    let mut obj = Map::new();
+    obj.insert("jsonrpc".to_owned(), Value::String("2.0".to_owned()));
+    obj.insert("method".to_owned(), Value::String(method.to_owned()));
+    if !params.is_null() {
+        obj.insert("params".to_owned(), params);
+    }
+    obj.insert("id".to_owned(), Value::from(id));
+    Value::Object(obj).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_request() {
+        assert_eq!(request(1, "ping", Value::Null), r#"{"id":1,"jsonrpc":"2.0","method":"ping"}"#);
+        assert_eq!(request(2, "add", json("[1, 2]")),
+                   r#"{"id":2,"jsonrpc":"2.0","method":"add","params":[1,2]}"#);
+    }
+
+    #[test]
+    fn test_response() {
+        assert_eq!(response(Value::from(1), Ok(Value::from(3))),
+                   json(r#"{"jsonrpc": "2.0", "result": 3, "id": 1}"#));
+        assert_eq!(response(Value::Null, Err(RpcError::method_not_found())),
+                   json(r#"{"jsonrpc": "2.0", "id": null,
+                            "error": {"code": -32601, "message": "Method not found"}}"#));
+    }
+
+    #[test]
+    fn test_is_rpc() {
+        let obj = |s: &str| json(s).as_object().unwrap().clone();
+        assert!(is_rpc(&obj(r#"{"jsonrpc": "2.0", "method": "add"}"#)));
+        assert!(!is_rpc(&obj(r#"{"jsonrpc": "1.0", "method": "add"}"#)));
+        assert!(!is_rpc(&obj(r#"{"method": "add"}"#)));
+    }
+}
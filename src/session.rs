@@ -1,6 +1,6 @@
 use actix::*;
 
-use protocol::Frame;
+use protocol::{CloseCode, Frame};
 use context::SockJSContext;
 
 /// Session state
@@ -46,7 +46,7 @@ pub enum SessionError {
     InternalError,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 /// Reason for closing session
 pub enum CloseReason {
     /// Session closed session
@@ -55,9 +55,23 @@ pub enum CloseReason {
     Expired,
     /// Peer get disconnected
     Interrupted,
+    /// Peer closed session, code and reason are supplied by peer
+    Peer {
+        code: Option<u16>,
+        reason: Option<String>,
+    },
+    /// Transport protocol error
+    ProtocolError(String),
+    /// Session get disconnected by server with `Disconnect` message,
+    /// close code and reason are sent to the peer
+    Kicked(CloseCode),
 }
 
 /// This trait defines sockjs session
+///
+/// Lifecycle callbacks and peer messages are handled in order:
+/// `opened()` comes first, followed by `acquired()`, peer messages
+/// are handled after it, and `closed()` is the last one.
 #[allow(unused_variables)]
 pub trait Session: Actor<Context=SockJSContext<Self>> + Default + Handler<Message> {
 
@@ -67,6 +81,9 @@ pub trait Session: Actor<Context=SockJSContext<Self>> + Default + Handler<Messag
     /// Method get called when transport acquires this session
     fn acquired(&mut self, ctx: &mut SockJSContext<Self>) {}
 
+    /// Method get called when transport resumes interrupted session
+    fn resumed(&mut self, ctx: &mut SockJSContext<Self>) {}
+
     /// Method get called when transport releases this session
     fn released(&mut self, ctx: &mut SockJSContext<Self>) {}
 
@@ -0,0 +1,199 @@
+//! Sharded session manager
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix::*;
+use futures::{future, Future};
+
+use bus::MemoryBus;
+use manager::{new_sid, Broadcast, CreateSession, Disconnect, DisconnectWhere, SendMessage,
+              SockJSManager, Subscribe};
+use presence::{PresenceUser, Roster};
+use protocol::Frame;
+use session::{Message, Session, SessionError};
+use topics::{Publish, SubscribeTopic};
+
+/// Index of the shard that owns session
+pub(crate) fn shard_index(sid: &str, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    sid.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// Handle to `SockJSManager` shards, every shard runs in its own arbiter.
+///
+/// Shard is selected by session id hash. Application created with
+/// `SockJS::sharded()` sends transport requests straight to the shard
+/// that owns the session, so the session actor runs in shard's arbiter.
+/// Shards are connected with in-process `MemoryBus`, so broadcasts,
+/// publications and messages to specific sessions reach sessions
+/// of every shard. Every shard keeps its own metrics.
+///
+/// ```rust,ignore
+/// let sm = ShardedManager::start(8, || {
+///     SockJSManager::<Chat>::default().disconnect_delay(Duration::new(30, 0))
+/// });
+/// let app = SockJS::sharded(&sm);
+/// ```
+pub struct ShardedManager<S: Session> {
+    shards: Arc<Vec<Addr<Syn, SockJSManager<S>>>>,
+    /// Shard for messages that are not bound to a session
+    next: Arc<AtomicUsize>,
+}
+
+impl<S: Session> Clone for ShardedManager<S> {
+    fn clone(&self) -> Self {
+        ShardedManager{shards: Arc::clone(&self.shards), next: Arc::clone(&self.next)}
+    }
+}
+
+impl<S: Session> ShardedManager<S> {
+    /// Start `shards` managers created with `factory`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero or if managers created by `factory`
+    /// are connected to a bus, shards use their own `MemoryBus`.
+    pub fn start<F>(shards: usize, factory: F) -> ShardedManager<S>
+        where F: Fn() -> SockJSManager<S> + Send + Sync + 'static
+    {
+        assert!(shards > 0, "At least one shard is required");
+
+        let factory = Arc::new(factory);
+        let bus = MemoryBus::new();
+        let (tx, rx) = mpsc::channel();
+        let shards: Vec<_> = (0..shards)
+            .map(|_| {
+                let factory = Arc::clone(&factory);
+                let bus = bus.clone();
+                let tx = tx.clone();
+                Arbiter::start(move |_| {
+                    let manager = (*factory)();
+                    let _ = tx.send(manager.has_bus());
+                    manager.bus(bus)
+                })
+            })
+            .collect();
+        drop(tx);
+
+        // managers are created in shard arbiters
+        for _ in 0..shards.len() {
+            assert!(!rx.recv().unwrap_or(false), "Shard managers can not be connected to a bus");
+        }
+        ShardedManager{shards: Arc::new(shards), next: Arc::new(AtomicUsize::new(0))}
+    }
+
+    /// Number of shards
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Shard that owns session
+    pub fn shard(&self, sid: &str) -> &Addr<Syn, SockJSManager<S>> {
+        &self.shards[shard_index(sid, self.shards.len())]
+    }
+
+    /// All shards
+    pub(crate) fn all(&self) -> &[Addr<Syn, SockJSManager<S>>] {
+        &self.shards
+    }
+
+    /// Shard for messages that get distributed with bus
+    fn any(&self) -> &Addr<Syn, SockJSManager<S>> {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.shards[next % self.shards.len()]
+    }
+
+    /// Send message to all sessions
+    pub fn broadcast<M: Into<Message>>(&self, message: M) {
+        // shard publishes broadcast to the bus
+        self.any().do_send(Broadcast::new(Frame::Message(message.into().0)));
+    }
+
+    /// Send message to specific session
+    pub fn send_message(&self, msg: SendMessage) {
+        self.shard(&msg.sid).do_send(msg);
+    }
+
+    /// Publish data to the topic
+    pub fn publish(&self, msg: Publish) {
+        self.any().do_send(msg);
+    }
+
+    /// Subscribe actor to the topic
+    pub fn subscribe_topic(&self, msg: SubscribeTopic) {
+        // publications of other shards reach subscriber through the bus
+        self.any().do_send(msg);
+    }
+
+    /// Subscribe to session lifecycle events of all shards
+    pub fn subscribe(&self, msg: Subscribe) {
+        for shard in self.shards.iter() {
+            shard.do_send(Subscribe(msg.0.clone()));
+        }
+    }
+
+    /// Create session on the shard that owns session id
+    pub fn create_session(&self, msg: CreateSession)
+                          -> Box<Future<Item=String, Error=SessionError>>
+    {
+        // shard is selected by session id, so it has to be known upfront
+        let sid = msg.sid.unwrap_or_else(new_sid);
+        Box::new(
+            self.shard(&sid).send(CreateSession{sid: Some(sid)})
+                .then(|res| match res {
+                    Ok(res) => res,
+                    Err(_) => Err(SessionError::InternalError),
+                }))
+    }
+
+    /// Close session with application defined code and reason
+    pub fn disconnect(&self, msg: Disconnect) -> Box<Future<Item=(), Error=()>> {
+        Box::new(
+            self.shard(&msg.sid).send(msg)
+                .then(|res| match res {
+                    Ok(res) => res,
+                    Err(_) => Err(()),
+                }))
+    }
+
+    /// Close sessions of all shards that match filter,
+    /// resolves with number of closed sessions
+    pub fn disconnect_where(&self, msg: DisconnectWhere) -> Box<Future<Item=usize, Error=()>> {
+        let counts: Vec<_> = self.shards.iter()
+            .map(|shard| shard.send(msg.clone())
+                 .then(|res| match res {
+                     Ok(res) => res,
+                     Err(_) => Err(()),
+                 }))
+            .collect();
+        Box::new(future::join_all(counts).map(|counts| counts.into_iter().sum::<usize>()))
+    }
+
+    /// Online users of all shards
+    pub fn roster(&self, msg: Roster) -> Box<Future<Item=Vec<PresenceUser>, Error=()>> {
+        let queries: Vec<_> = self.shards.iter()
+            .map(|shard| shard.send(Roster{room: msg.room.clone()})
+                 .then(|res| match res {
+                     Ok(res) => res,
+                     Err(_) => Err(()),
+                 }))
+            .collect();
+
+        // user's sessions can live in several shards
+        Box::new(future::join_all(queries).map(|rosters| {
+            let mut users: HashMap<String, PresenceUser> = HashMap::new();
+            for user in rosters.into_iter().flat_map(|r| r) {
+                let merged = users.entry(user.user.clone())
+                    .or_insert_with(|| PresenceUser{sessions: 0, ..user.clone()});
+                merged.sessions += user.sessions;
+            }
+            let mut users: Vec<_> = users.into_iter().map(|(_, u)| u).collect();
+            users.sort_by(|a, b| a.user.cmp(&b.user));
+            users
+        }))
+    }
+}
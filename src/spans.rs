@@ -0,0 +1,100 @@
+//! Structured tracing spans
+//!
+//! Every session gets `sockjs_session` span with session id, every
+//! transport request that acquires session gets `sockjs_request` child span.
+//! Spans and events are recorded only if `tracing` feature is enabled,
+//! otherwise all functions are no-op.
+#[cfg(feature = "tracing")]
+pub use self::enabled::*;
+#[cfg(not(feature = "tracing"))]
+pub use self::noop::*;
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use tracing::{self, Level};
+
+    use protocol::Frame;
+    use session::SessionState;
+
+    /// Session or transport request span
+    #[derive(Clone, Debug)]
+    pub struct Span(tracing::Span);
+
+    /// Session span
+    pub fn session(sid: &str) -> Span {
+        Span(span!(Level::INFO, "sockjs_session", sid = sid))
+    }
+
+    /// Transport request span, child of session span
+    pub fn request(session: &Span, transport: &'static str) -> Span {
+        Span(span!(parent: &session.0, Level::INFO, "sockjs_request", transport = transport))
+    }
+
+    /// Transport acquired session
+    pub fn acquired(span: &Span, transport: &'static str) {
+        event!(parent: &span.0, Level::DEBUG, transport = transport, "session acquired");
+    }
+
+    /// Transport released session
+    pub fn released(span: &Span, state: &SessionState) {
+        event!(parent: &span.0, Level::DEBUG, state = ?state, "session released");
+    }
+
+    /// Session state changed
+    pub fn state(span: &Span, from: &SessionState, to: &SessionState) {
+        event!(parent: &span.0, Level::DEBUG, from = ?from, to = ?to, "session state changed");
+    }
+
+    /// Heartbeat sent to the peer
+    pub fn heartbeat(span: &Span) {
+        event!(parent: &span.0, Level::TRACE, "heartbeat");
+    }
+
+    /// Frame sent to the peer, only heartbeat and close frames are recorded
+    pub fn frame(span: &Span, frm: &Frame) {
+        match *frm {
+            Frame::Heartbeat => heartbeat(span),
+            Frame::Close(ref code) => {
+                event!(parent: &span.0, Level::DEBUG,
+                       code = code.num(), reason = code.reason(), "close frame sent");
+            }
+            _ => (),
+        }
+    }
+
+    /// Buffered frames get flushed to the transport
+    pub fn flush(span: &Span, frames: usize) {
+        if frames != 0 {
+            event!(parent: &span.0, Level::DEBUG, frames = frames, "buffer flushed");
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod noop {
+    use protocol::Frame;
+    use session::SessionState;
+
+    #[derive(Clone, Debug)]
+    pub struct Span;
+
+    pub fn session(_: &str) -> Span {
+        Span
+    }
+
+    pub fn request(_: &Span, _: &'static str) -> Span {
+        Span
+    }
+
+    pub fn acquired(_: &Span, _: &'static str) {}
+
+    pub fn released(_: &Span, _: &SessionState) {}
+
+    pub fn state(_: &Span, _: &SessionState, _: &SessionState) {}
+
+    pub fn heartbeat(_: &Span) {}
+
+    pub fn frame(_: &Span, _: &Frame) {}
+
+    pub fn flush(_: &Span, _: usize) {}
+}
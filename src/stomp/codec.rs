@@ -0,0 +1,405 @@
+use std::fmt;
+
+/// Default limit of incomplete frame size
+const MAX_FRAME_SIZE: usize = 131_072;
+
+/// Stomp command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StompCommand {
+    Connect,
+    Stomp,
+    Connected,
+    Send,
+    Subscribe,
+    Unsubscribe,
+    Ack,
+    Nack,
+    Begin,
+    Commit,
+    Abort,
+    Disconnect,
+    Message,
+    Receipt,
+    Error,
+}
+
+impl StompCommand {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            StompCommand::Connect => "CONNECT",
+            StompCommand::Stomp => "STOMP",
+            StompCommand::Connected => "CONNECTED",
+            StompCommand::Send => "SEND",
+            StompCommand::Subscribe => "SUBSCRIBE",
+            StompCommand::Unsubscribe => "UNSUBSCRIBE",
+            StompCommand::Ack => "ACK",
+            StompCommand::Nack => "NACK",
+            StompCommand::Begin => "BEGIN",
+            StompCommand::Commit => "COMMIT",
+            StompCommand::Abort => "ABORT",
+            StompCommand::Disconnect => "DISCONNECT",
+            StompCommand::Message => "MESSAGE",
+            StompCommand::Receipt => "RECEIPT",
+            StompCommand::Error => "ERROR",
+        }
+    }
+
+    fn parse(s: &str) -> Option<StompCommand> {
+        Some(match s {
+            "CONNECT" => StompCommand::Connect,
+            "STOMP" => StompCommand::Stomp,
+            "CONNECTED" => StompCommand::Connected,
+            "SEND" => StompCommand::Send,
+            "SUBSCRIBE" => StompCommand::Subscribe,
+            "UNSUBSCRIBE" => StompCommand::Unsubscribe,
+            "ACK" => StompCommand::Ack,
+            "NACK" => StompCommand::Nack,
+            "BEGIN" => StompCommand::Begin,
+            "COMMIT" => StompCommand::Commit,
+            "ABORT" => StompCommand::Abort,
+            "DISCONNECT" => StompCommand::Disconnect,
+            "MESSAGE" => StompCommand::Message,
+            "RECEIPT" => StompCommand::Receipt,
+            "ERROR" => StompCommand::Error,
+            _ => return None,
+        })
+    }
+
+    /// Headers of `CONNECT` and `CONNECTED` frames are not escaped
+    fn escaped(&self) -> bool {
+        match *self {
+            StompCommand::Connect | StompCommand::Connected => false,
+            _ => true,
+        }
+    }
+}
+
+/// Stomp frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct StompFrame {
+    pub command: StompCommand,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl StompFrame {
+    pub fn new(command: StompCommand) -> StompFrame {
+        StompFrame{command, headers: Vec::new(), body: String::new()}
+    }
+
+    /// Add header
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set frame body
+    pub fn body<T: Into<String>>(mut self, body: T) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Header value, if header is repeated first value is used
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.0 == name).map(|h| h.1.as_str())
+    }
+
+    /// Encode frame, `content-length` header is added for non empty body
+    pub fn encode(&self) -> String {
+        let escaped = self.command.escaped();
+        let mut s = String::with_capacity(self.body.len() + 64);
+        s.push_str(self.command.as_str());
+        s.push('\n');
+        for &(ref name, ref value) in &self.headers {
+            if escaped {
+                escape(name, &mut s);
+                s.push(':');
+                escape(value, &mut s);
+            } else {
+                s.push_str(name);
+                s.push(':');
+                s.push_str(value);
+            }
+            s.push('\n');
+        }
+        if !self.body.is_empty() && self.get("content-length").is_none() {
+            s.push_str(&format!("content-length:{}\n", self.body.len()));
+        }
+        s.push('\n');
+        s.push_str(&self.body);
+        s.push('\0');
+        s
+    }
+}
+
+/// Decoded item
+#[derive(Debug, PartialEq)]
+pub enum StompItem {
+    Frame(StompFrame),
+    /// End of line between frames
+    Heartbeat,
+}
+
+/// Stomp frame decoding error
+#[derive(Clone, Debug, PartialEq)]
+pub enum StompError {
+    UnknownCommand(String),
+    MalformedHeader(String),
+    InvalidEscape(String),
+    InvalidContentLength,
+    /// Body is not terminated with NULL octet
+    MissingTerminator,
+    /// Frame exceeds maximum frame size
+    FrameTooLarge,
+}
+
+impl fmt::Display for StompError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StompError::UnknownCommand(ref cmd) => write!(f, "Unknown command: {}", cmd),
+            StompError::MalformedHeader(ref h) => write!(f, "Malformed header: {}", h),
+            StompError::InvalidEscape(ref h) => write!(f, "Invalid escape sequence: {}", h),
+            StompError::InvalidContentLength => write!(f, "Invalid content-length header"),
+            StompError::MissingTerminator => write!(f, "Frame body is not terminated"),
+            StompError::FrameTooLarge => write!(f, "Frame is too large"),
+        }
+    }
+}
+
+/// Stomp frame decoder
+///
+/// Single frame can be split across several sockjs messages,
+/// incomplete frame is kept until rest of the frame arrives.
+pub struct StompCodec {
+    buf: String,
+    max_size: usize,
+}
+
+impl Default for StompCodec {
+    fn default() -> StompCodec {
+        StompCodec{buf: String::new(), max_size: MAX_FRAME_SIZE}
+    }
+}
+
+impl StompCodec {
+    pub fn new() -> StompCodec {
+        StompCodec::default()
+    }
+
+    /// Set maximum size of incomplete frame.
+    ///
+    /// By default it is 128Kb.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_size = size;
+        self
+    }
+
+    /// Decode all complete frames
+    pub fn decode(&mut self, data: &str) -> Result<Vec<StompItem>, StompError> {
+        self.buf.push_str(data);
+        let mut items = Vec::new();
+        let mut pos = 0;
+
+        loop {
+            // heartbeats
+            let rest = &self.buf[pos..];
+            let trimmed = rest.trim_start_matches(|c| c == '\n' || c == '\r');
+            if trimmed.len() != rest.len() {
+                items.push(StompItem::Heartbeat);
+                pos += rest.len() - trimmed.len();
+            }
+            if trimmed.is_empty() {
+                break
+            }
+
+            match decode_frame(trimmed)? {
+                Some((frame, size)) => {
+                    items.push(StompItem::Frame(frame));
+                    pos += size;
+                }
+                None => break,
+            }
+        }
+        self.buf.drain(..pos);
+        if self.buf.len() > self.max_size {
+            self.buf.clear();
+            return Err(StompError::FrameTooLarge)
+        }
+        Ok(items)
+    }
+}
+
+/// Decode single frame, returns frame and number of consumed bytes
+/// or `None` if frame is not complete
+fn decode_frame(src: &str) -> Result<Option<(StompFrame, usize)>, StompError> {
+    let mut lines = 0;
+    let mut headers_end = None;
+    let mut start = 0;
+    let mut command = None;
+    let mut headers = Vec::new();
+
+    // command and headers
+    while let Some(idx) = src[start..].find('\n') {
+        let line = src[start..start + idx].trim_end_matches('\r');
+        let next = start + idx + 1;
+        if lines == 0 {
+            command = Some(StompCommand::parse(line)
+                           .ok_or_else(|| StompError::UnknownCommand(line.to_owned()))?);
+        } else if line.is_empty() {
+            headers_end = Some(next);
+            break
+        } else {
+            let escaped = command.map(|c| c.escaped()).unwrap_or(true);
+            headers.push(decode_header(line, escaped)?);
+        }
+        lines += 1;
+        start = next;
+    }
+    let (command, body_start) = match (command, headers_end) {
+        (Some(command), Some(end)) => (command, end),
+        _ => return Ok(None),
+    };
+
+    // body
+    let length = match headers.iter().find(|h: &&(String, String)| h.0 == "content-length") {
+        Some(h) => Some(h.1.parse::<usize>().map_err(|_| StompError::InvalidContentLength)?),
+        None => None,
+    };
+    let body_end = match length {
+        Some(length) => {
+            let end = body_start + length;
+            if src.len() <= end {
+                return Ok(None)
+            }
+            if !src.is_char_boundary(end) || src.as_bytes()[end] != 0 {
+                return Err(StompError::MissingTerminator)
+            }
+            end
+        }
+        None => match src[body_start..].find('\0') {
+            Some(idx) => body_start + idx,
+            None => return Ok(None),
+        },
+    };
+
+    let frame = StompFrame{command, headers, body: src[body_start..body_end].to_owned()};
+    Ok(Some((frame, body_end + 1)))
+}
+
+fn decode_header(line: &str, escaped: bool) -> Result<(String, String), StompError> {
+    let idx = match line.find(':') {
+        Some(idx) => idx,
+        None => return Err(StompError::MalformedHeader(line.to_owned())),
+    };
+    let (name, value) = (&line[..idx], &line[idx + 1..]);
+    if escaped {
+        Ok((unescape(name)?, unescape(value)?))
+    } else {
+        Ok((name.to_owned(), value.to_owned()))
+    }
+}
+
+fn escape(s: &str, dst: &mut String) {
+    for c in s.chars() {
+        match c {
+            '\\' => dst.push_str("\\\\"),
+            '\r' => dst.push_str("\\r"),
+            '\n' => dst.push_str("\\n"),
+            ':' => dst.push_str("\\c"),
+            c => dst.push(c),
+        }
+    }
+}
+
+fn unescape(s: &str) -> Result<String, StompError> {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('\\') => res.push('\\'),
+                Some('r') => res.push('\r'),
+                Some('n') => res.push('\n'),
+                Some('c') => res.push(':'),
+                _ => return Err(StompError::InvalidEscape(s.to_owned())),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(items: Vec<StompItem>) -> Vec<StompFrame> {
+        items.into_iter().filter_map(|item| match item {
+            StompItem::Frame(frame) => Some(frame),
+            StompItem::Heartbeat => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_header_escaping() {
+        let frame = StompFrame::new(StompCommand::Send)
+            .header("destination", "a:b\nc\\d\re");
+        let encoded = frame.encode();
+        assert_eq!(encoded, "SEND\ndestination:a\\cb\\nc\\\\d\\re\n\n\0");
+
+        let decoded = frames(StompCodec::new().decode(&encoded).unwrap());
+        assert_eq!(decoded, vec![frame]);
+
+        // connect headers are not escaped
+        let decoded = frames(StompCodec::new().decode("CONNECT\nlogin:a\\c\n\n\0").unwrap());
+        assert_eq!(decoded[0].get("login"), Some("a\\c"));
+
+        assert_eq!(StompCodec::new().decode("SEND\ndestination:a\\t\n\n\0"),
+                   Err(StompError::InvalidEscape("a\\t".to_owned())));
+    }
+
+    #[test]
+    fn test_content_length() {
+        let frame = StompFrame::new(StompCommand::Send).header("destination", "d").body("a\0b");
+        let encoded = frame.encode();
+        assert!(encoded.contains("content-length:3\n"));
+
+        let decoded = frames(StompCodec::new().decode(&encoded).unwrap());
+        assert_eq!(decoded[0].body, "a\0b");
+
+        assert_eq!(StompCodec::new().decode("SEND\ncontent-length:x\n\nab\0"),
+                   Err(StompError::InvalidContentLength));
+        assert_eq!(StompCodec::new().decode("SEND\ncontent-length:1\n\nab\0"),
+                   Err(StompError::MissingTerminator));
+    }
+
+    #[test]
+    fn test_split_frames() {
+        let mut codec = StompCodec::new();
+        assert_eq!(codec.decode("\nSEND\r\ndestin").unwrap(), vec![StompItem::Heartbeat]);
+        assert!(codec.decode("ation:d\n\nhel").unwrap().is_empty());
+
+        let items = codec.decode("lo\0\nSUBSCRIBE\nid:1\n").unwrap();
+        assert_eq!(items, vec![
+            StompItem::Frame(StompFrame::new(StompCommand::Send)
+                             .header("destination", "d").body("hello")),
+            StompItem::Heartbeat,
+        ]);
+
+        let items = frames(codec.decode("destination:d\n\n\0").unwrap());
+        assert_eq!(items[0].command, StompCommand::Subscribe);
+        assert_eq!(items[0].get("id"), Some("1"));
+    }
+
+    #[test]
+    fn test_max_frame_size() {
+        let mut codec = StompCodec::new().max_frame_size(24);
+        assert!(codec.decode("SEND\ndestination:d\n\n").unwrap().is_empty());
+        assert_eq!(codec.decode("0123456789"), Err(StompError::FrameTooLarge));
+
+        // complete frames are not limited by incomplete frame size
+        let msg = format!("SEND\ndestination:d\n\n{}\0", "x".repeat(32));
+        assert_eq!(frames(codec.decode(&msg).unwrap())[0].body.len(), 32);
+    }
+}
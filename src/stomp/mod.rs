@@ -0,0 +1,10 @@
+//! Stomp over sockjs
+//!
+//! [STOMP 1.2](https://stomp.github.io/stomp-specification-1.2.html) frame
+//! codec and broker session, so off-the-shelf stomp clients (stomp.js etc)
+//! can talk to sockjs server. Available with `stomp` feature.
+mod codec;
+mod session;
+
+pub use self::codec::{StompCodec, StompCommand, StompError, StompFrame, StompItem};
+pub use self::session::StompSession;
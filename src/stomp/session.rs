@@ -0,0 +1,313 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::*;
+use serde_json::Value;
+
+use context::SockJSContext;
+use session::{Message, Session};
+use topics::{ActorTopic, Publication};
+
+use super::codec::{StompCodec, StompCommand, StompFrame, StompItem};
+
+const VERSIONS: [&str; 3] = ["1.2", "1.1", "1.0"];
+
+/// Default limit of unacknowledged messages per subscription
+const MAX_PENDING: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AckMode {
+    Auto,
+    Client,
+    ClientIndividual,
+}
+
+struct Subscription {
+    destination: String,
+    ack: AckMode,
+    /// Message ids that are not acknowledged yet
+    pending: Vec<String>,
+}
+
+/// Stomp broker session
+///
+/// Destinations are manager topics, frames sent to destination get
+/// published with `Publish` message, so sessions of the manager (and of
+/// other managers connected with broadcast bus) that subscribed
+/// to destination receive `MESSAGE` frames. Transactions are not supported,
+/// `NACK` frames drop messages without redelivery. Session get closed
+/// with `ERROR` frame if client does not acknowledge messages.
+///
+/// ```rust,ignore
+/// let sm: Addr<Syn, _> = SockJSManager::<StompSession>::start_default();
+/// ```
+pub struct StompSession {
+    codec: StompCodec,
+    connected: bool,
+    subscriptions: HashMap<String, Subscription>,
+    /// Destinations this session is subscribed to at manager
+    topics: HashSet<String>,
+    /// Heartbeat interval server is able to send, in milliseconds
+    heartbeat: u64,
+    message_id: u64,
+    max_pending: usize,
+}
+
+impl Default for StompSession {
+    fn default() -> StompSession {
+        StompSession::new(10_000)
+    }
+}
+
+impl Actor for StompSession {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for StompSession {}
+
+impl StompSession {
+    /// Create session that sends heartbeats every `heartbeat` milliseconds
+    /// if client asks for them, `0` disables heartbeats
+    pub fn new(heartbeat: u64) -> StompSession {
+        StompSession {
+            heartbeat,
+            codec: StompCodec::new(),
+            connected: false,
+            subscriptions: HashMap::new(),
+            topics: HashSet::new(),
+            message_id: 0,
+            max_pending: MAX_PENDING,
+        }
+    }
+
+    /// Set limit of unacknowledged messages per subscription.
+    ///
+    /// By default it is 1024 messages.
+    pub fn max_pending(mut self, max: usize) -> Self {
+        self.max_pending = max;
+        self
+    }
+
+    /// Set maximum size of incomplete frame.
+    ///
+    /// By default it is 128Kb.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.codec = self.codec.max_frame_size(size);
+        self
+    }
+
+    fn send(&self, frame: StompFrame, ctx: &mut SockJSContext<Self>) {
+        ctx.send(frame.encode());
+    }
+
+    /// Send `ERROR` frame and close session
+    fn error(&mut self, message: &str, frame: Option<&StompFrame>, ctx: &mut SockJSContext<Self>) {
+        let mut err = StompFrame::new(StompCommand::Error).header("message", message);
+        if let Some(receipt) = frame.and_then(|f| f.get("receipt")) {
+            err = err.header("receipt-id", receipt);
+        }
+        self.send(err, ctx);
+        ctx.close();
+    }
+
+    fn handle_frame(&mut self, frame: StompFrame, ctx: &mut SockJSContext<Self>) {
+        match frame.command {
+            StompCommand::Connect | StompCommand::Stomp => {
+                return self.connect(&frame, ctx)
+            }
+            _ if !self.connected => {
+                return self.error("Not connected", Some(&frame), ctx)
+            }
+            StompCommand::Subscribe => {
+                let (destination, id) = match (frame.get("destination"), frame.get("id")) {
+                    (Some(destination), Some(id)) => (destination.to_owned(), id.to_owned()),
+                    _ => return self.error(
+                        "SUBSCRIBE requires destination and id headers", Some(&frame), ctx),
+                };
+                let ack = match frame.get("ack") {
+                    None | Some("auto") => AckMode::Auto,
+                    Some("client") => AckMode::Client,
+                    Some("client-individual") => AckMode::ClientIndividual,
+                    Some(_) => return self.error("Unknown ack mode", Some(&frame), ctx),
+                };
+                if self.subscriptions.contains_key(&id) {
+                    return self.error("Subscription id is already in use", Some(&frame), ctx)
+                }
+                if !self.topics.contains(&destination) {
+                    self.topics.insert(destination.clone());
+                    let addr: Addr<Syn, _> = ctx.address();
+                    ctx.manager().do_send(ActorTopic{
+                        sid: Arc::clone(ctx.sid()),
+                        topic: destination.clone(),
+                        subscriber: Some(addr.recipient())});
+                }
+                self.subscriptions.insert(
+                    id, Subscription{destination, ack, pending: Vec::new()});
+            }
+            StompCommand::Unsubscribe => {
+                let sub = match frame.get("id") {
+                    Some(id) => self.subscriptions.remove(id),
+                    None => return self.error(
+                        "UNSUBSCRIBE requires id header", Some(&frame), ctx),
+                };
+                // last subscription to destination
+                if let Some(sub) = sub {
+                    if !self.subscriptions.values().any(|s| s.destination == sub.destination) {
+                        self.topics.remove(&sub.destination);
+                        ctx.manager().do_send(ActorTopic{
+                            sid: Arc::clone(ctx.sid()), topic: sub.destination, subscriber: None});
+                    }
+                }
+            }
+            StompCommand::Send => {
+                match frame.get("destination") {
+                    Some(destination) => {
+                        ctx.publish(destination, Value::String(frame.body.clone()));
+                    }
+                    None => return self.error(
+                        "SEND requires destination header", Some(&frame), ctx),
+                }
+            }
+            StompCommand::Ack | StompCommand::Nack => {
+                match frame.get("id") {
+                    Some(id) => self.ack(id),
+                    None => return self.error(
+                        "ACK and NACK require id header", Some(&frame), ctx),
+                }
+            }
+            StompCommand::Begin | StompCommand::Commit | StompCommand::Abort => {
+                return self.error("Transactions are not supported", Some(&frame), ctx)
+            }
+            StompCommand::Disconnect => {
+                if let Some(receipt) = frame.get("receipt") {
+                    self.send(StompFrame::new(StompCommand::Receipt)
+                              .header("receipt-id", receipt), ctx);
+                }
+                self.connected = false;
+                return ctx.close()
+            }
+            StompCommand::Connected | StompCommand::Message |
+            StompCommand::Receipt | StompCommand::Error => {
+                return self.error("Unexpected server frame", Some(&frame), ctx)
+            }
+        }
+
+        if let Some(receipt) = frame.get("receipt") {
+            self.send(StompFrame::new(StompCommand::Receipt).header("receipt-id", receipt), ctx);
+        }
+    }
+
+    fn connect(&mut self, frame: &StompFrame, ctx: &mut SockJSContext<Self>) {
+        if self.connected {
+            return self.error("Already connected", Some(frame), ctx)
+        }
+        let accepted: Vec<_> = frame.get("accept-version").unwrap_or("1.0")
+            .split(',').map(|v| v.trim()).collect();
+        let version = match VERSIONS.iter().find(|v| accepted.contains(*v)) {
+            Some(version) => *version,
+            None => {
+                let err = StompFrame::new(StompCommand::Error)
+                    .header("version", VERSIONS.join(","))
+                    .header("content-type", "text/plain")
+                    .body(format!("Supported protocol versions are {}", VERSIONS.join(" ")));
+                self.send(err, ctx);
+                return ctx.close()
+            }
+        };
+
+        // client's heart-beat header is `<cx>,<cy>`, `cy` is desired interval
+        // of server heartbeats
+        let cy = frame.get("heart-beat")
+            .and_then(|hb| hb.split(',').nth(1))
+            .and_then(|cy| cy.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let sx = if cy == 0 { 0 } else { self.heartbeat };
+        if sx != 0 {
+            let interval = cmp::max(sx, cy);
+            ctx.run_interval(Duration::from_millis(interval), |_, ctx| ctx.send("\n"));
+        }
+
+        self.connected = true;
+        let connected = StompFrame::new(StompCommand::Connected)
+            .header("version", version)
+            .header("heart-beat", format!("{},0", sx))
+            .header("server", concat!("sockjs/", env!("CARGO_PKG_VERSION")))
+            .header("session", ctx.sid().as_str());
+        self.send(connected, ctx);
+    }
+
+    fn ack(&mut self, id: &str) {
+        for sub in self.subscriptions.values_mut() {
+            if let Some(idx) = sub.pending.iter().position(|p| p == id) {
+                match sub.ack {
+                    // cumulative
+                    AckMode::Client => { sub.pending.drain(..idx + 1); }
+                    _ => { sub.pending.remove(idx); }
+                }
+                return
+            }
+        }
+    }
+}
+
+impl Handler<Message> for StompSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        let items = match self.codec.decode(&msg.0) {
+            Ok(items) => items,
+            Err(err) => return self.error(&err.to_string(), None, ctx),
+        };
+        for item in items {
+            if let StompItem::Frame(frame) = item {
+                self.handle_frame(frame, ctx);
+            }
+        }
+    }
+}
+
+impl Handler<Publication> for StompSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Publication, ctx: &mut SockJSContext<Self>) {
+        if !self.connected {
+            return
+        }
+        let (body, content_type) = match msg.data {
+            Value::String(ref s) => (s.clone(), "text/plain"),
+            ref data => (data.to_string(), "application/json"),
+        };
+
+        let mut frames = Vec::new();
+        let mut overflow = false;
+        for (id, sub) in &mut self.subscriptions {
+            if sub.destination != msg.topic {
+                continue
+            }
+            self.message_id += 1;
+            let message_id = self.message_id.to_string();
+            let mut frame = StompFrame::new(StompCommand::Message)
+                .header("subscription", id.as_str())
+                .header("message-id", message_id.as_str())
+                .header("destination", msg.topic.as_str())
+                .header("content-type", content_type);
+            if sub.ack != AckMode::Auto {
+                if sub.pending.len() >= self.max_pending {
+                    overflow = true;
+                    break
+                }
+                frame = frame.header("ack", message_id.as_str());
+                sub.pending.push(message_id);
+            }
+            frames.push(frame.body(body.as_str()));
+        }
+        for frame in frames {
+            self.send(frame, ctx);
+        }
+        if overflow {
+            self.error("Too many unacknowledged messages", None, ctx);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use serde_json;
+
+use super::{SessionStore, StoredSession};
+
+/// Writes that are not on disk yet, `None` stands for removal
+type Pending = Arc<Mutex<HashMap<String, (u64, Option<StoredSession>)>>>;
+
+/// File backed session store
+///
+/// Every session is stored as json file in the store directory.
+/// Files are written by background thread, consecutive writes
+/// of the same session are coalesced.
+pub struct FileStore {
+    dir: PathBuf,
+    pending: Pending,
+    tx: Sender<()>,
+}
+
+impl FileStore {
+    /// Open store, directory get created if it does not exist
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<FileStore> {
+        fs::create_dir_all(dir.as_ref())?;
+        let dir = dir.as_ref().to_owned();
+        let pending = Pending::default();
+        let (tx, rx) = channel();
+
+        let (d, p) = (dir.clone(), Arc::clone(&pending));
+        thread::Builder::new()
+            .name("sockjs-store".to_owned())
+            .spawn(move || {
+                // drains pending writes after store get dropped
+                while rx.recv().is_ok() || !p.lock().unwrap().is_empty() {
+                    while rx.try_recv().is_ok() {}
+                    flush(&d, &p);
+                }
+            })?;
+        Ok(FileStore{dir, pending, tx})
+    }
+
+    fn enqueue(&self, sid: &str, session: Option<StoredSession>) {
+        // generation is bumped under the lock, so writer can tell stale entries
+        let mut pending = self.pending.lock().unwrap();
+        let generation = pending.get(sid).map(|e| e.0 + 1).unwrap_or(0);
+        pending.insert(sid.to_owned(), (generation, session));
+        let _ = self.tx.send(());
+    }
+}
+
+fn path(dir: &Path, sid: &str) -> PathBuf {
+    // session id is supplied by peer, hex encode it
+    let name: String = sid.bytes().map(|b| format!("{:02x}", b)).collect();
+    dir.join(format!("{}.json", name))
+}
+
+fn write(dir: &Path, session: &StoredSession) -> io::Result<()> {
+    let path = path(dir, &session.sid);
+    let tmp = path.with_extension("tmp");
+    {
+        let mut f = File::create(&tmp)?;
+        f.write_all(&serde_json::to_vec(session)?)?;
+        f.sync_all()?;
+    }
+    fs::rename(tmp, path)
+}
+
+fn remove(dir: &Path, sid: &str) -> io::Result<()> {
+    match fs::remove_file(path(dir, sid)) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+/// Write pending sessions to disk
+fn flush(dir: &Path, pending: &Pending) {
+    let batch: Vec<_> = pending.lock().unwrap().iter()
+        .map(|(sid, entry)| (sid.clone(), entry.clone()))
+        .collect();
+
+    for (sid, (generation, session)) in batch {
+        let res = match session {
+            Some(ref session) => write(dir, session),
+            None => remove(dir, &sid),
+        };
+        if let Err(err) = res {
+            error!("Can not write session {}: {}", sid, err);
+        }
+        // session could be updated meanwhile
+        let mut pending = pending.lock().unwrap();
+        if pending.get(&sid).map(|e| e.0) == Some(generation) {
+            pending.remove(&sid);
+        }
+    }
+}
+
+impl SessionStore for FileStore {
+    fn save(&self, session: &StoredSession) -> io::Result<()> {
+        self.enqueue(&session.sid, Some(session.clone()));
+        Ok(())
+    }
+
+    fn load(&self, sid: &str) -> io::Result<Option<StoredSession>> {
+        if let Some(&(_, ref session)) = self.pending.lock().unwrap().get(sid) {
+            return Ok(session.clone())
+        }
+        match File::open(path(&self.dir, sid)) {
+            Ok(f) => Ok(Some(serde_json::from_reader(f)?)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn remove(&self, sid: &str) -> io::Result<()> {
+        self.enqueue(sid, None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::time::Duration;
+
+    use rand;
+
+    use super::*;
+    use store::StoredFrame;
+
+    fn session(sid: &str, msg: &str) -> StoredSession {
+        let mut session = StoredSession::new(sid);
+        session.frames.push(StoredFrame::Message(msg.to_owned()));
+        session
+    }
+
+    fn frames(session: Option<StoredSession>) -> Vec<String> {
+        session.map(|s| s.frames.into_iter().map(|frm| match frm {
+            StoredFrame::Message(s) | StoredFrame::Messages(s) => s,
+        }).collect()).unwrap_or_default()
+    }
+
+    /// Load session from disk, bypassing pending writes
+    fn load_from_disk(dir: &Path, sid: &str) -> Vec<String> {
+        let store = FileStore::new(dir).unwrap();
+        frames(store.load(sid).unwrap())
+    }
+
+    fn wait_for<F: Fn() -> bool>(f: F) {
+        for _ in 0..100 {
+            if f() {
+                return
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("store is not flushed");
+    }
+
+    fn tempdir() -> PathBuf {
+        env::temp_dir().join(format!("sockjs-store-{}", rand::random::<u64>()))
+    }
+
+    #[test]
+    fn test_save_load_remove() {
+        let dir = tempdir();
+        let store = FileStore::new(&dir).unwrap();
+
+        // pending write is visible immediately
+        store.save(&session("../sid", "hello")).unwrap();
+        assert_eq!(frames(store.load("../sid").unwrap()), vec!["hello"]);
+        wait_for(|| load_from_disk(&dir, "../sid") == vec!["hello"]);
+        assert!(path(&dir, "../sid").starts_with(&dir));
+
+        store.remove("../sid").unwrap();
+        assert!(store.load("../sid").unwrap().is_none());
+        wait_for(|| load_from_disk(&dir, "../sid").is_empty());
+        assert!(store.load("unknown").unwrap().is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_last_write_wins() {
+        let dir = tempdir();
+        {
+            let store = FileStore::new(&dir).unwrap();
+            for i in 0..100 {
+                store.save(&session("sid", &i.to_string())).unwrap();
+            }
+            assert_eq!(frames(store.load("sid").unwrap()), vec!["99"]);
+        }
+        // dropped store flushes pending writes
+        wait_for(|| load_from_disk(&dir, "sid") == vec!["99"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
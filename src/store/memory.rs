@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use super::{SessionStore, StoredSession};
+
+/// In-memory session store
+///
+/// Clones of the store share sessions, so sessions survive restart
+/// of the manager within one process.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<HashMap<String, StoredSession>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn save(&self, session: &StoredSession) -> io::Result<()> {
+        self.sessions.lock().unwrap().insert(session.sid.clone(), session.clone());
+        Ok(())
+    }
+
+    fn load(&self, sid: &str) -> io::Result<Option<StoredSession>> {
+        Ok(self.sessions.lock().unwrap().get(sid).cloned())
+    }
+
+    fn remove(&self, sid: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().remove(sid);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_sessions() {
+        let store = MemoryStore::new();
+        store.clone().save(&StoredSession::new("sid")).unwrap();
+        assert_eq!(store.load("sid").unwrap().map(|s| s.sid), Some("sid".to_owned()));
+
+        store.clone().remove("sid").unwrap();
+        assert!(store.load("sid").unwrap().is_none());
+    }
+}
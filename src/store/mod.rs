@@ -0,0 +1,88 @@
+//! Session store persists pending frames of idle sessions,
+//! so sessions survive manager restart.
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use protocol::Frame;
+
+mod file;
+mod memory;
+
+pub use self::file::FileStore;
+pub use self::memory::MemoryStore;
+
+/// Persisted session
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredSession {
+    /// Session id
+    pub sid: String,
+    /// Time session got released by transport, seconds since unix epoch
+    pub released: u64,
+    /// Frames that are not delivered to the peer yet
+    pub frames: Vec<StoredFrame>,
+}
+
+impl StoredSession {
+    pub(crate) fn new(sid: &str) -> StoredSession {
+        StoredSession {
+            sid: sid.to_owned(),
+            released: now(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Seconds elapsed since session got released
+    pub fn elapsed(&self) -> u64 {
+        now().saturating_sub(self.released)
+    }
+}
+
+/// Persisted frame
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StoredFrame {
+    /// Single message
+    Message(String),
+    /// Json encoded list of messages
+    Messages(String),
+}
+
+impl StoredFrame {
+    pub(crate) fn from_frame(frm: &Frame) -> Option<StoredFrame> {
+        match *frm {
+            Frame::Message(ref s) => Some(StoredFrame::Message(s.clone())),
+            Frame::MessageVec(ref s) => Some(StoredFrame::Messages(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl From<StoredFrame> for Frame {
+    fn from(frm: StoredFrame) -> Frame {
+        match frm {
+            StoredFrame::Message(s) => Frame::Message(s),
+            StoredFrame::Messages(s) => Frame::MessageVec(s),
+        }
+    }
+}
+
+/// Session store
+///
+/// Manager saves session every time transport releases it or frame get
+/// buffered for idle session, and removes session when it get closed or expired.
+pub trait SessionStore: Send + 'static {
+    /// Save session
+    fn save(&self, session: &StoredSession) -> io::Result<()>;
+
+    /// Load session
+    fn load(&self, sid: &str) -> io::Result<Option<StoredSession>>;
+
+    /// Remove session
+    fn remove(&self, sid: &str) -> io::Result<()>;
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,316 @@
+//! Pub/sub topics
+//!
+//! If topics are enabled with `SockJSManager::topics()`, peer messages
+//! that are topic envelopes get routed by the manager instead of
+//! being delivered to the session:
+//!
+//! ```json
+//! {"type": "subscribe", "topic": "news"}
+//! {"type": "unsubscribe", "topic": "news"}
+//! {"type": "publish", "topic": "news", "data": {"title": "..."}}
+//! ```
+//!
+//! Subscribed peers receive publications as
+//! `{"type": "publication", "topic": "news", "data": {...}}` messages.
+//! All other messages are delivered to the session as usual.
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+
+use actix::{Recipient, SendError, Syn};
+use serde_json::{self, Value};
+
+/// Topic envelope sent by peer
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum Envelope {
+    Subscribe { topic: String },
+    Unsubscribe { topic: String },
+    Publish { topic: String, #[serde(default)] data: Value },
+}
+
+impl Envelope {
+    /// Parse peer message, returns `None` if message is not topic envelope
+    pub fn parse(msg: &str) -> Option<Envelope> {
+        if !msg.starts_with('{') {
+            return None
+        }
+        serde_json::from_str(msg).ok()
+    }
+}
+
+/// Publication to the topic
+///
+/// Actors subscribed with `SubscribeTopic` receive publications
+/// as actix messages.
+#[derive(Clone, Debug, PartialEq, Message, Serialize, Deserialize)]
+pub struct Publication {
+    pub topic: String,
+    pub data: Value,
+}
+
+impl Publication {
+    pub fn new<T: Into<String>>(topic: T, data: Value) -> Publication {
+        Publication{topic: topic.into(), data}
+    }
+
+    /// Message sent to subscribed peers
+    pub(crate) fn to_message(&self) -> String {
+        serde_json::to_string(&Outgoing{
+            kind: "publication", topic: &self.topic, data: &self.data}).unwrap()
+    }
+}
+
+#[derive(Serialize)]
+struct Outgoing<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    topic: &'a str,
+    data: &'a Value,
+}
+
+/// Publish data to the topic
+#[derive(Debug, Message)]
+pub struct Publish(pub Publication);
+
+/// Subscribe actor to the topic
+#[derive(Message)]
+pub struct SubscribeTopic {
+    pub topic: String,
+    pub subscriber: Recipient<Syn, Publication>,
+}
+
+/// Subscribe or unsubscribe session actor to the topic,
+/// subscription is dropped when session get closed
+#[derive(Message)]
+pub(crate) struct ActorTopic {
+    pub sid: Arc<String>,
+    pub topic: String,
+    /// `None` unsubscribes
+    pub subscriber: Option<Recipient<Syn, Publication>>,
+}
+
+/// Subscribe or unsubscribe session to the topic
+#[derive(Debug, Message)]
+pub(crate) struct SessionTopic {
+    pub sid: Arc<String>,
+    pub topic: String,
+    pub subscribe: bool,
+}
+
+/// Topic subscriptions
+#[derive(Default)]
+pub(crate) struct TopicRouter {
+    peers: HashMap<String, HashSet<Arc<String>>>,
+    /// Subscribed actors, owner session is set for session actors
+    actors: HashMap<String, Vec<(Option<Arc<String>>, Recipient<Syn, Publication>)>>,
+    sessions: HashMap<Arc<String>, HashSet<String>>,
+}
+
+impl TopicRouter {
+    pub fn subscribe(&mut self, sid: &Arc<String>, topic: String) {
+        self.sessions.entry(Arc::clone(sid)).or_insert_with(HashSet::new).insert(topic.clone());
+        self.peers.entry(topic).or_insert_with(HashSet::new).insert(Arc::clone(sid));
+    }
+
+    pub fn unsubscribe(&mut self, sid: &Arc<String>, topic: &str) {
+        if let Some(topics) = self.sessions.get_mut(sid) {
+            topics.remove(topic);
+        }
+        let empty = if let Some(peers) = self.peers.get_mut(topic) {
+            peers.remove(sid);
+            peers.is_empty()
+        } else {
+            false
+        };
+        if empty {
+            self.peers.remove(topic);
+        }
+    }
+
+    pub fn subscribe_actor(&mut self, topic: String, subscriber: Recipient<Syn, Publication>) {
+        self.actors.entry(topic).or_insert_with(Vec::new).push((None, subscriber));
+    }
+
+    /// Subscribe session actor, session actor is subscribed at most once
+    pub fn subscribe_session_actor(&mut self, sid: &Arc<String>, topic: String,
+                                   subscriber: Recipient<Syn, Publication>) {
+        self.unsubscribe_session_actor(sid, &topic);
+        self.actors.entry(topic).or_insert_with(Vec::new)
+            .push((Some(Arc::clone(sid)), subscriber));
+    }
+
+    pub fn unsubscribe_session_actor(&mut self, sid: &Arc<String>, topic: &str) {
+        let empty = if let Some(actors) = self.actors.get_mut(topic) {
+            actors.retain(|a| a.0.as_ref() != Some(sid));
+            actors.is_empty()
+        } else {
+            false
+        };
+        if empty {
+            self.actors.remove(topic);
+        }
+    }
+
+    /// Remove all subscriptions of the session
+    pub fn remove(&mut self, sid: &Arc<String>) {
+        if let Some(topics) = self.sessions.remove(sid) {
+            for topic in topics {
+                self.unsubscribe(sid, &topic);
+            }
+        }
+        for actors in self.actors.values_mut() {
+            actors.retain(|a| a.0.as_ref() != Some(sid));
+        }
+        self.actors.retain(|_, actors| !actors.is_empty());
+    }
+
+    /// Deliver publication to subscribed actors and return subscribed sessions
+    pub fn publish(&mut self, msg: &Publication) -> Vec<Arc<String>> {
+        let empty = if let Some(actors) = self.actors.get_mut(&msg.topic) {
+            actors.retain(|a| match a.1.do_send(msg.clone()) {
+                Err(SendError::Closed(_)) => false,
+                _ => true,
+            });
+            actors.is_empty()
+        } else {
+            false
+        };
+        if empty {
+            self.actors.remove(&msg.topic);
+        }
+
+        match self.peers.get(&msg.topic) {
+            Some(peers) => peers.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use actix::{Actor, Addr, Context, Handler, System};
+
+    use super::*;
+
+    fn sid(s: &str) -> Arc<String> {
+        Arc::new(s.to_owned())
+    }
+
+    fn sorted(sids: Vec<Arc<String>>) -> Vec<String> {
+        let mut sids: Vec<_> = sids.iter().map(|s| s.to_string()).collect();
+        sids.sort();
+        sids
+    }
+
+    fn publication(topic: &str, data: &str) -> Publication {
+        Publication::new(topic, Value::from(data))
+    }
+
+    type Received = Arc<Mutex<Vec<String>>>;
+
+    struct Collector(Received);
+
+    impl Actor for Collector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Publication> for Collector {
+        type Result = ();
+
+        fn handle(&mut self, msg: Publication, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(format!("{} {}", msg.topic, msg.data));
+        }
+    }
+
+    /// Subscriber that stops right away
+    struct Gone;
+
+    impl Actor for Gone {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Context<Self>) {
+            ctx.stop();
+        }
+    }
+
+    impl Handler<Publication> for Gone {
+        type Result = ();
+
+        fn handle(&mut self, _: Publication, _: &mut Context<Self>) {}
+    }
+
+    #[test]
+    fn test_parse() {
+        match Envelope::parse(r#"{"type":"subscribe","topic":"news"}"#) {
+            Some(Envelope::Subscribe{ref topic}) if topic == "news" => (),
+            env => panic!("unexpected envelope {:?}", env),
+        }
+        match Envelope::parse(r#"{"type":"publish","topic":"news"}"#) {
+            Some(Envelope::Publish{ref topic, ref data}) if topic == "news" && data.is_null() => (),
+            env => panic!("unexpected envelope {:?}", env),
+        }
+        assert!(Envelope::parse("hello").is_none());
+        assert!(Envelope::parse(r#"{"type":"join","topic":"news"}"#).is_none());
+        assert!(Envelope::parse(r#"{"topic":"news"}"#).is_none());
+    }
+
+    #[test]
+    fn test_to_message() {
+        assert_eq!(publication("news", "hi").to_message(),
+                   r#"{"type":"publication","topic":"news","data":"hi"}"#);
+    }
+
+    #[test]
+    fn test_session_subscriptions() {
+        let mut router = TopicRouter::default();
+        let (s1, s2) = (sid("s1"), sid("s2"));
+        router.subscribe(&s1, "news".to_owned());
+        router.subscribe(&s1, "news".to_owned());
+        router.subscribe(&s2, "news".to_owned());
+        router.subscribe(&s2, "sport".to_owned());
+        assert_eq!(sorted(router.publish(&publication("news", ""))), vec!["s1", "s2"]);
+        assert!(router.publish(&publication("weather", "")).is_empty());
+
+        router.unsubscribe(&s1, "news");
+        assert_eq!(sorted(router.publish(&publication("news", ""))), vec!["s2"]);
+
+        router.remove(&s2);
+        assert!(router.publish(&publication("news", "")).is_empty());
+        assert!(router.publish(&publication("sport", "")).is_empty());
+        assert!(router.peers.is_empty());
+    }
+
+    #[test]
+    fn test_actor_subscriptions() {
+        let mut sys = System::new("test");
+        let received = Received::default();
+        let collector: Addr<Syn, _> = Collector(Arc::clone(&received)).start();
+        let gone: Addr<Syn, _> = Gone.start();
+        let s1 = sid("s1");
+
+        let mut router = TopicRouter::default();
+        router.subscribe_actor("news".to_owned(), gone.recipient());
+        router.subscribe_session_actor(&s1, "news".to_owned(), collector.clone().recipient());
+        // session actor is subscribed at most once
+        router.subscribe_session_actor(&s1, "news".to_owned(), collector.clone().recipient());
+        router.subscribe_session_actor(&s1, "sport".to_owned(), collector.clone().recipient());
+
+        // let stopped subscriber drop its mailbox
+        let _ = sys.run_until_complete(collector.send(publication("sync", "")));
+        assert!(router.publish(&publication("news", "a")).is_empty());
+        assert_eq!(router.actors["news"].len(), 1);
+
+        router.unsubscribe_session_actor(&s1, "news");
+        router.publish(&publication("news", "b"));
+        router.publish(&publication("sport", "c"));
+        router.remove(&s1);
+        router.publish(&publication("sport", "d"));
+        assert!(router.actors.is_empty());
+
+        let _ = sys.run_until_complete(collector.send(publication("sync", "")));
+        assert_eq!(*received.lock().unwrap(),
+                   vec!["sync \"\"", "news \"a\"", "sport \"c\"", "sync \"\""]);
+    }
+}
@@ -0,0 +1,663 @@
+//! `permessage-deflate` websocket extension, RFC 7692
+//!
+//! Extension is negotiated only if `permessage-deflate` feature is enabled
+//! and `SockJS::permessage_deflate()` is set, otherwise websocket transports
+//! use plain frames.
+#[cfg(feature = "permessage-deflate")]
+pub use self::enabled::*;
+#[cfg(not(feature = "permessage-deflate"))]
+pub use self::noop::*;
+
+/// `permessage-deflate` extension parameters
+#[cfg_attr(not(feature = "permessage-deflate"), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct DeflateConfig {
+    server_max_window_bits: u8,
+    client_max_window_bits: u8,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+    threshold: usize,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> DeflateConfig {
+        DeflateConfig {
+            server_max_window_bits: 15,
+            client_max_window_bits: 15,
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            threshold: 256,
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "permessage-deflate"), allow(dead_code))]
+impl DeflateConfig {
+    pub fn new() -> DeflateConfig {
+        DeflateConfig::default()
+    }
+
+    /// LZ77 window size server uses for compression, `9-15`.
+    ///
+    /// By default it is 15.
+    pub fn server_max_window_bits(mut self, bits: u8) -> Self {
+        self.server_max_window_bits = clamp_bits(bits);
+        self
+    }
+
+    /// LZ77 window size client is asked to use for compression, `9-15`.
+    ///
+    /// Client that does not support `client_max_window_bits` parameter
+    /// uses 15. By default it is 15.
+    pub fn client_max_window_bits(mut self, bits: u8) -> Self {
+        self.client_max_window_bits = clamp_bits(bits);
+        self
+    }
+
+    /// Reset server compression context after every message.
+    ///
+    /// Saves memory per connection at the cost of compression ratio.
+    pub fn server_no_context_takeover(mut self, val: bool) -> Self {
+        self.server_no_context_takeover = val;
+        self
+    }
+
+    /// Ask client to reset compression context after every message.
+    pub fn client_no_context_takeover(mut self, val: bool) -> Self {
+        self.client_no_context_takeover = val;
+        self
+    }
+
+    /// Messages smaller than `size` bytes are sent uncompressed.
+    ///
+    /// By default it is 256 bytes.
+    pub fn threshold(mut self, size: usize) -> Self {
+        self.threshold = size;
+        self
+    }
+}
+
+/// zlib does not support 8 bits window for raw deflate streams
+#[cfg_attr(not(feature = "permessage-deflate"), allow(dead_code))]
+fn clamp_bits(bits: u8) -> u8 {
+    if bits < 9 {
+        9
+    } else if bits > 15 {
+        15
+    } else {
+        bits
+    }
+}
+
+#[cfg(feature = "permessage-deflate")]
+mod enabled {
+    use std::str;
+
+    use actix::*;
+    use actix_web::*;
+    use actix_web::dev::HttpResponseBuilder;
+    use actix_web::error::PayloadError;
+    use bytes::{BufMut, Bytes, BytesMut};
+    use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+    use futures::{Async, Poll, Stream};
+    use http::header::{HeaderValue, SEC_WEBSOCKET_EXTENSIONS};
+
+    use super::DeflateConfig;
+
+    /// Max size of inbound message
+    const MAX_SIZE: usize = 131_072;
+
+    const TAIL: [u8; 4] = [0, 0, 0xff, 0xff];
+
+    /// Negotiated extension parameters
+    struct Params {
+        server_bits: u8,
+        client_bits: u8,
+        server_no_context_takeover: bool,
+        client_no_context_takeover: bool,
+    }
+
+    /// Pick first offer from `Sec-WebSocket-Extensions` header we can accept
+    fn negotiate(header: &str, cfg: &DeflateConfig) -> Option<Params> {
+        'offers: for offer in header.split(',') {
+            let mut parts = offer.split(';').map(|s| s.trim());
+            if parts.next() != Some("permessage-deflate") {
+                continue
+            }
+            let mut params = Params {
+                server_bits: cfg.server_max_window_bits,
+                client_bits: 15,
+                server_no_context_takeover: cfg.server_no_context_takeover,
+                client_no_context_takeover: cfg.client_no_context_takeover,
+            };
+            for param in parts {
+                let mut kv = param.splitn(2, '=');
+                let name = kv.next().unwrap_or("").trim();
+                let value = kv.next().map(|v| v.trim().trim_matches('"'));
+                match (name, value) {
+                    ("server_no_context_takeover", None) =>
+                        params.server_no_context_takeover = true,
+                    ("client_no_context_takeover", None) =>
+                        params.client_no_context_takeover = true,
+                    // zlib can not use 8 bits window, offer is declined
+                    ("server_max_window_bits", Some(bits)) => match bits.parse::<u8>() {
+                        Ok(bits) if bits >= 9 && bits <= 15 =>
+                            params.server_bits = ::std::cmp::min(params.server_bits, bits),
+                        _ => continue 'offers,
+                    },
+                    // client accepts any value we pick
+                    ("client_max_window_bits", None) =>
+                        params.client_bits = cfg.client_max_window_bits,
+                    ("client_max_window_bits", Some(bits)) => match bits.parse::<u8>() {
+                        Ok(bits) if bits >= 9 && bits <= 15 =>
+                            params.client_bits = ::std::cmp::min(
+                                cfg.client_max_window_bits, bits),
+                        _ => continue 'offers,
+                    },
+                    _ => continue 'offers,
+                }
+            }
+            return Some(params)
+        }
+        None
+    }
+
+    impl Params {
+        /// Response `Sec-WebSocket-Extensions` header value
+        fn header(&self) -> String {
+            let mut s = "permessage-deflate".to_owned();
+            if self.server_no_context_takeover {
+                s.push_str("; server_no_context_takeover");
+            }
+            if self.client_no_context_takeover {
+                s.push_str("; client_no_context_takeover");
+            }
+            if self.server_bits != 15 {
+                s.push_str(&format!("; server_max_window_bits={}", self.server_bits));
+            }
+            if self.client_bits != 15 {
+                s.push_str(&format!("; client_max_window_bits={}", self.client_bits));
+            }
+            s
+        }
+    }
+
+    /// Compressor for outbound messages
+    pub struct Deflater {
+        compress: Compress,
+        no_context_takeover: bool,
+        threshold: usize,
+    }
+
+    impl Deflater {
+        fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+            let mut out = Vec::with_capacity(data.len() / 2 + 64);
+            let start = self.compress.total_in();
+            loop {
+                let consumed = (self.compress.total_in() - start) as usize;
+                if out.capacity() - out.len() < 64 {
+                    out.reserve(data.len() / 2 + 64);
+                }
+                if self.compress.compress_vec(
+                    &data[consumed..], &mut out, FlushCompress::Sync).is_err()
+                {
+                    break
+                }
+                let consumed = (self.compress.total_in() - start) as usize;
+                if consumed == data.len() && out.len() < out.capacity() {
+                    break
+                }
+            }
+            if out.ends_with(&TAIL) {
+                let len = out.len() - TAIL.len();
+                out.truncate(len);
+            }
+            if self.no_context_takeover {
+                self.compress.reset();
+            }
+            out
+        }
+    }
+
+    /// Decompressor for inbound messages
+    pub struct Inflater {
+        decompress: Decompress,
+        no_context_takeover: bool,
+    }
+
+    impl Inflater {
+        fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, ws::ProtocolError> {
+            let mut input = Vec::with_capacity(data.len() + TAIL.len());
+            input.extend_from_slice(data);
+            input.extend_from_slice(&TAIL);
+
+            let mut out = Vec::with_capacity(data.len() * 3);
+            let start = self.decompress.total_in();
+            loop {
+                let consumed = (self.decompress.total_in() - start) as usize;
+                if out.capacity() - out.len() < 64 {
+                    out.reserve(data.len() * 2 + 64);
+                }
+                if self.decompress.decompress_vec(
+                    &input[consumed..], &mut out, FlushDecompress::Sync).is_err()
+                {
+                    return Err(corrupted())
+                }
+                if out.len() > MAX_SIZE {
+                    return Err(ws::ProtocolError::Overflow)
+                }
+                let consumed = (self.decompress.total_in() - start) as usize;
+                if consumed == input.len() && out.len() < out.capacity() {
+                    break
+                }
+            }
+            if self.no_context_takeover {
+                self.decompress.reset(false);
+            }
+            Ok(out)
+        }
+    }
+
+    fn corrupted() -> ws::ProtocolError {
+        ws::ProtocolError::Payload(PayloadError::EncodingCorrupted)
+    }
+
+    /// Negotiate extension and start reading websocket frames.
+    ///
+    /// Returns compressor for outbound messages if extension is accepted.
+    pub fn start<A, S>(req: &HttpRequest<S>, resp: &mut HttpResponseBuilder,
+                       ctx: &mut ws::WebsocketContext<A, S>, cfg: Option<&DeflateConfig>)
+                       -> Option<Deflater>
+        where A: Actor<Context=ws::WebsocketContext<A, S>> +
+                 StreamHandler<ws::Message, ws::ProtocolError>,
+              S: 'static,
+    {
+        let params = cfg.and_then(|cfg| {
+            req.headers().get(SEC_WEBSOCKET_EXTENSIONS)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| negotiate(h, cfg))
+                .map(|params| (params, cfg.threshold))
+        });
+
+        match params {
+            Some((params, threshold)) => {
+                if let Ok(val) = HeaderValue::from_str(&params.header()) {
+                    resp.header(SEC_WEBSOCKET_EXTENSIONS, val);
+                }
+                let inflater = Inflater {
+                    decompress: Decompress::new_with_window_bits(false, params.client_bits),
+                    no_context_takeover: params.client_no_context_takeover,
+                };
+                ctx.add_stream(DeflateStream::new(req.clone(), inflater));
+                Some(Deflater {
+                    threshold,
+                    compress: Compress::new_with_window_bits(
+                        Compression::default(), false, params.server_bits),
+                    no_context_takeover: params.server_no_context_takeover,
+                })
+            }
+            None => {
+                ctx.add_stream(ws::WsStream::new(req.clone()));
+                None
+            }
+        }
+    }
+
+    /// Send text message, compressed if extension is negotiated
+    /// and message is not smaller than threshold
+    pub fn text<A, S, T>(ctx: &mut ws::WebsocketContext<A, S>,
+                         deflater: &mut Option<Deflater>, text: T)
+        where A: Actor<Context=ws::WebsocketContext<A, S>>,
+              T: Into<String>,
+    {
+        let text = text.into();
+        match *deflater {
+            Some(ref mut deflater) if text.len() >= deflater.threshold => {
+                let payload = deflater.compress(text.as_bytes());
+                ctx.write_raw(encode(0x1, &payload, true));
+            }
+            _ => ctx.text(text),
+        }
+    }
+
+    /// Encode unmasked server frame
+    fn encode(opcode: u8, payload: &[u8], rsv1: bool) -> Binary {
+        let mut buf = BytesMut::with_capacity(payload.len() + 10);
+        buf.put_u8(0x80 | if rsv1 { 0x40 } else { 0 } | opcode);
+        if payload.len() < 126 {
+            buf.put_u8(payload.len() as u8);
+        } else if payload.len() <= 65_535 {
+            buf.put_u8(126);
+            buf.put_u16_be(payload.len() as u16);
+        } else {
+            buf.put_u8(127);
+            buf.put_u64_be(payload.len() as u64);
+        }
+        buf.put_slice(payload);
+        buf.freeze().into()
+    }
+
+    /// Websocket frames stream that decompresses messages with RSV1 bit set
+    pub struct DeflateStream<S> {
+        rx: S,
+        buf: BytesMut,
+        inflater: Inflater,
+        /// Opcode, compression flag and payload of fragmented message
+        partial: Option<(u8, bool, BytesMut)>,
+        closed: bool,
+    }
+
+    impl<S> DeflateStream<S> where S: Stream<Item=Bytes, Error=PayloadError> {
+        fn new(rx: S, inflater: Inflater) -> Self {
+            DeflateStream{rx, inflater, buf: BytesMut::new(), partial: None, closed: false}
+        }
+
+        /// Parse frames from buffer until complete message is available
+        fn parse(&mut self) -> Result<Option<ws::Message>, ws::ProtocolError> {
+            loop {
+                if self.buf.len() < 2 {
+                    return Ok(None)
+                }
+                let first = self.buf[0];
+                let second = self.buf[1];
+                let fin = first & 0x80 != 0;
+                let rsv1 = first & 0x40 != 0;
+                let opcode = first & 0x0f;
+
+                if second & 0x80 == 0 {
+                    return Err(ws::ProtocolError::UnmaskedFrame)
+                }
+                // RSV2 and RSV3 are not negotiated
+                if first & 0x30 != 0 {
+                    return Err(ws::ProtocolError::BadOpcode)
+                }
+                // control frames can not be fragmented or compressed
+                if opcode & 0x08 != 0 {
+                    if !fin || rsv1 {
+                        return Err(ws::ProtocolError::BadOpcode)
+                    }
+                    if second & 0x7f > 125 {
+                        return Err(ws::ProtocolError::Overflow)
+                    }
+                }
+                let (len, mut idx) = match second & 0x7f {
+                    126 => {
+                        if self.buf.len() < 4 {
+                            return Ok(None)
+                        }
+                        ((usize::from(self.buf[2]) << 8) | usize::from(self.buf[3]), 4)
+                    }
+                    127 => {
+                        if self.buf.len() < 10 {
+                            return Ok(None)
+                        }
+                        let mut len: u64 = 0;
+                        for b in &self.buf[2..10] {
+                            len = (len << 8) | u64::from(*b);
+                        }
+                        if len > MAX_SIZE as u64 {
+                            return Err(ws::ProtocolError::Overflow)
+                        }
+                        (len as usize, 10)
+                    }
+                    len => (len as usize, 2),
+                };
+                if len > MAX_SIZE {
+                    return Err(ws::ProtocolError::Overflow)
+                }
+                if self.buf.len() < idx + 4 + len {
+                    return Ok(None)
+                }
+
+                let mut mask = [0u8; 4];
+                mask.copy_from_slice(&self.buf[idx..idx + 4]);
+                idx += 4;
+                let mut frame = self.buf.split_to(idx + len);
+                frame.advance(idx);
+                for (i, b) in frame.iter_mut().enumerate() {
+                    *b ^= mask[i % 4];
+                }
+
+                match opcode {
+                    // continuation
+                    0x0 => {
+                        if rsv1 {
+                            return Err(ws::ProtocolError::BadOpcode)
+                        }
+                        let done = match self.partial {
+                            Some((_, _, ref mut payload)) => {
+                                if payload.len() + frame.len() > MAX_SIZE {
+                                    return Err(ws::ProtocolError::Overflow)
+                                }
+                                payload.extend_from_slice(&frame);
+                                fin
+                            }
+                            None => return Err(ws::ProtocolError::BadOpcode),
+                        };
+                        if done {
+                            let (opcode, compressed, payload) = self.partial.take().unwrap();
+                            return self.message(opcode, compressed, payload).map(Some)
+                        }
+                    }
+                    // text and binary
+                    0x1 | 0x2 => {
+                        if self.partial.is_some() {
+                            return Err(ws::ProtocolError::BadOpcode)
+                        }
+                        if fin {
+                            return self.message(opcode, rsv1, frame).map(Some)
+                        }
+                        self.partial = Some((opcode, rsv1, frame));
+                    }
+                    // close
+                    0x8 => {
+                        if frame.len() >= 2 {
+                            let code = (u16::from(frame[0]) << 8) | u16::from(frame[1]);
+                            let description = str::from_utf8(&frame[2..]).ok()
+                                .filter(|s| !s.is_empty())
+                                .map(|s| s.to_owned());
+                            return Ok(Some(ws::Message::Close(Some(ws::CloseReason{
+                                code: code.into(), description}))))
+                        }
+                        return Ok(Some(ws::Message::Close(None)))
+                    }
+                    0x9 => return Ok(Some(
+                        ws::Message::Ping(String::from_utf8_lossy(&frame).into_owned()))),
+                    0xa => return Ok(Some(
+                        ws::Message::Pong(String::from_utf8_lossy(&frame).into_owned()))),
+                    _ => return Err(ws::ProtocolError::InvalidOpcode(opcode)),
+                }
+            }
+        }
+
+        fn message(&mut self, opcode: u8, compressed: bool, payload: BytesMut)
+                   -> Result<ws::Message, ws::ProtocolError>
+        {
+            let payload = if compressed {
+                BytesMut::from(self.inflater.decompress(&payload)?)
+            } else {
+                payload
+            };
+            if opcode == 0x1 {
+                match String::from_utf8(payload.to_vec()) {
+                    Ok(s) => Ok(ws::Message::Text(s)),
+                    Err(_) => Err(corrupted()),
+                }
+            } else {
+                Ok(ws::Message::Binary(payload.freeze().into()))
+            }
+        }
+    }
+
+    impl<S> Stream for DeflateStream<S> where S: Stream<Item=Bytes, Error=PayloadError> {
+        type Item = ws::Message;
+        type Error = ws::ProtocolError;
+
+        fn poll(&mut self) -> Poll<Option<ws::Message>, ws::ProtocolError> {
+            loop {
+                if let Some(msg) = self.parse()? {
+                    return Ok(Async::Ready(Some(msg)))
+                }
+                if self.closed {
+                    return Ok(Async::Ready(None))
+                }
+                match self.rx.poll().map_err(ws::ProtocolError::Payload)? {
+                    Async::Ready(Some(chunk)) => self.buf.extend_from_slice(&chunk),
+                    Async::Ready(None) => self.closed = true,
+                    Async::NotReady => return Ok(Async::NotReady),
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use futures::stream;
+
+        use super::*;
+
+        /// Masked client frame
+        fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+            let mask = [1u8, 2, 3, 4];
+            let mut buf = vec![first];
+            if payload.len() < 126 {
+                buf.push(0x80 | payload.len() as u8);
+            } else {
+                buf.push(0x80 | 126);
+                buf.push((payload.len() >> 8) as u8);
+                buf.push(payload.len() as u8);
+            }
+            buf.extend_from_slice(&mask);
+            buf.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+            buf
+        }
+
+        fn deflater() -> Deflater {
+            Deflater{compress: Compress::new_with_window_bits(Compression::default(), false, 15),
+                     no_context_takeover: false,
+                     threshold: 0}
+        }
+
+        fn read(frames: &[Vec<u8>]) -> Vec<Result<ws::Message, ws::ProtocolError>> {
+            let chunks: Vec<_> = frames.iter().map(|f| Bytes::from(f.clone())).collect();
+            let inflater = Inflater{decompress: Decompress::new_with_window_bits(false, 15),
+                                    no_context_takeover: false};
+            let stream = DeflateStream::new(stream::iter_ok::<_, PayloadError>(chunks), inflater);
+
+            // stream keeps failing after protocol error
+            let mut msgs = Vec::new();
+            for msg in stream.wait() {
+                let failed = msg.is_err();
+                msgs.push(msg);
+                if failed {
+                    break
+                }
+            }
+            msgs
+        }
+
+        fn messages(frames: &[Vec<u8>]) -> Vec<ws::Message> {
+            read(frames).into_iter().map(|msg| msg.unwrap()).collect()
+        }
+
+        fn is_err(frames: &[Vec<u8>]) -> bool {
+            read(frames).last().map(|msg| msg.is_err()).unwrap_or(false)
+        }
+
+        #[test]
+        fn test_negotiate_window_bits() {
+            let cfg = DeflateConfig::new();
+            let params = negotiate("permessage-deflate; server_max_window_bits=10; \
+                                    client_max_window_bits=12", &cfg).unwrap();
+            assert_eq!((params.server_bits, params.client_bits), (10, 12));
+
+            // 8 bits window is declined, next offer is accepted
+            assert!(negotiate("permessage-deflate; server_max_window_bits=8", &cfg).is_none());
+            assert!(negotiate("permessage-deflate; client_max_window_bits=8", &cfg).is_none());
+            let params = negotiate("permessage-deflate; client_max_window_bits=8, \
+                                    permessage-deflate", &cfg).unwrap();
+            assert_eq!(params.header(), "permessage-deflate");
+        }
+
+        #[test]
+        fn test_fragmented() {
+            // control frame can be interleaved with fragments
+            let msgs = messages(&[frame(0x01, b"hel"), frame(0x89, b"p"), frame(0x80, b"lo")]);
+            assert_eq!(msgs, vec![ws::Message::Ping("p".to_owned()),
+                                  ws::Message::Text("hello".to_owned())]);
+
+            // continuation without first fragment
+            assert!(is_err(&[frame(0x80, b"lo")]));
+            // new message before last fragment
+            assert!(is_err(&[frame(0x01, b"hel"), frame(0x81, b"lo")]));
+        }
+
+        #[test]
+        fn test_compressed() {
+            let mut deflater = deflater();
+            let text = "hello ".repeat(50);
+            let payload = deflater.compress(text.as_bytes());
+            assert!(payload.len() < text.len());
+
+            // context is kept between messages
+            let second = deflater.compress(text.as_bytes());
+            let (head, tail) = second.split_at(second.len() / 2);
+            let msgs = messages(&[frame(0xc1, &payload), frame(0x41, head), frame(0x80, tail),
+                                  frame(0x81, b"plain")]);
+            assert_eq!(msgs, vec![ws::Message::Text(text.clone()),
+                                  ws::Message::Text(text),
+                                  ws::Message::Text("plain".to_owned())]);
+
+            // only first fragment carries RSV1
+            assert!(is_err(&[frame(0x41, head), frame(0xc0, tail)]));
+            assert!(is_err(&[frame(0xc1, b"not deflate")]));
+        }
+
+        #[test]
+        fn test_control_frames() {
+            assert_eq!(messages(&[frame(0x8a, b"pong")]),
+                       vec![ws::Message::Pong("pong".to_owned())]);
+            // fragmented
+            assert!(is_err(&[frame(0x09, b"ping")]));
+            // compressed
+            assert!(is_err(&[frame(0xc9, b"ping")]));
+            // too large
+            assert!(is_err(&[frame(0x89, &[b'p'; 126])]));
+        }
+
+        #[test]
+        fn test_reserved_bits() {
+            assert!(is_err(&[frame(0xa1, b"rsv2")]));
+            assert!(is_err(&[frame(0x91, b"rsv3")]));
+        }
+    }
+}
+
+#[cfg(not(feature = "permessage-deflate"))]
+mod noop {
+    use actix::*;
+    use actix_web::*;
+    use actix_web::dev::HttpResponseBuilder;
+
+    use super::DeflateConfig;
+
+    pub enum Deflater {}
+
+    pub fn start<A, S>(req: &HttpRequest<S>, _: &mut HttpResponseBuilder,
+                       ctx: &mut ws::WebsocketContext<A, S>, _: Option<&DeflateConfig>)
+                       -> Option<Deflater>
+        where A: Actor<Context=ws::WebsocketContext<A, S>> +
+                 StreamHandler<ws::Message, ws::ProtocolError>,
+              S: 'static,
+    {
+        ctx.add_stream(ws::WsStream::new(req.clone()));
+        None
+    }
+
+    pub fn text<A, S, T>(ctx: &mut ws::WebsocketContext<A, S>, _: &mut Option<Deflater>, text: T)
+        where A: Actor<Context=ws::WebsocketContext<A, S>>,
+              T: Into<String>,
+    {
+        ctx.text(text.into());
+    }
+}
@@ -0,0 +1,239 @@
+//! Response compression for streaming transports
+//!
+//! Response is compressed only if `compress` feature is enabled,
+//! compression is enabled with `SockJS::compression()` and client accepts
+//! `gzip` or `deflate` encoding. Encoder is flushed after every frame,
+//! so compression does not delay frames.
+use actix_web::Binary;
+use actix_web::dev::HttpResponseBuilder;
+
+#[cfg(feature = "compress")]
+pub use self::enabled::StreamEncoder;
+#[cfg(not(feature = "compress"))]
+pub use self::noop::StreamEncoder;
+
+impl StreamEncoder {
+    /// Set response headers for negotiated encoding
+    pub fn headers(&self, resp: &mut HttpResponseBuilder) {
+        use actix_web::http::ContentEncoding;
+        use actix_web::http::header::{CONTENT_ENCODING, VARY};
+
+        if let Some(name) = self.encoding() {
+            // frames are compressed by transport, not by actix
+            resp.content_encoding(ContentEncoding::Identity)
+                .header(CONTENT_ENCODING, name)
+                .header(VARY, "Accept-Encoding");
+        }
+    }
+
+    /// Encode and flush chunk of the response
+    pub fn chunk<B: Into<Binary>>(&mut self, data: B) -> Binary {
+        self.encode(data.into())
+    }
+}
+
+#[cfg(feature = "compress")]
+mod enabled {
+    use std::io::Write;
+    use std::mem;
+
+    use actix_web::*;
+    use actix_web::http::header::ACCEPT_ENCODING;
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+
+    enum Encoder {
+        Gzip(GzEncoder<Vec<u8>>),
+        Deflate(ZlibEncoder<Vec<u8>>),
+    }
+
+    /// Streaming response encoder
+    pub struct StreamEncoder(Option<Encoder>);
+
+    impl StreamEncoder {
+        /// Pick encoding from `Accept-Encoding` request header
+        pub fn negotiate<S>(req: &HttpRequest<S>, enabled: bool) -> StreamEncoder {
+            if !enabled {
+                return StreamEncoder(None)
+            }
+            let accept = match req.headers().get(ACCEPT_ENCODING).and_then(|h| h.to_str().ok()) {
+                Some(accept) => accept,
+                None => return StreamEncoder(None),
+            };
+
+            let mut gzip = false;
+            let mut deflate = false;
+            for item in accept.split(',') {
+                let mut parts = item.split(';').map(|s| s.trim());
+                let name = parts.next().unwrap_or("");
+                let refused = parts.any(|p| {
+                    p.starts_with("q=") && p[2..].parse::<f32>().map(|q| q == 0.0).unwrap_or(false)
+                });
+                if refused {
+                    continue
+                }
+                match name {
+                    "gzip" => gzip = true,
+                    "deflate" => deflate = true,
+                    _ => (),
+                }
+            }
+
+            if gzip {
+                StreamEncoder(Some(
+                    Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default()))))
+            } else if deflate {
+                StreamEncoder(Some(
+                    Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default()))))
+            } else {
+                StreamEncoder(None)
+            }
+        }
+
+        /// Negotiated `Content-Encoding`
+        pub fn encoding(&self) -> Option<&'static str> {
+            match self.0 {
+                Some(Encoder::Gzip(_)) => Some("gzip"),
+                Some(Encoder::Deflate(_)) => Some("deflate"),
+                None => None,
+            }
+        }
+
+        pub(super) fn encode(&mut self, data: Binary) -> Binary {
+            let res = match self.0 {
+                Some(Encoder::Gzip(ref mut enc)) => enc.write_all(data.as_ref())
+                    .and_then(|_| enc.flush())
+                    .map(|_| mem::replace(enc.get_mut(), Vec::new())),
+                Some(Encoder::Deflate(ref mut enc)) => enc.write_all(data.as_ref())
+                    .and_then(|_| enc.flush())
+                    .map(|_| mem::replace(enc.get_mut(), Vec::new())),
+                None => return data,
+            };
+            match res {
+                Ok(buf) => buf.into(),
+                Err(err) => {
+                    error!("Can not compress response: {}", err);
+                    Binary::from(Vec::new())
+                }
+            }
+        }
+
+        /// Complete compressed stream, returns stream trailer
+        pub fn finish(&mut self) -> Option<Binary> {
+            let res = match self.0.take() {
+                Some(Encoder::Gzip(enc)) => enc.finish(),
+                Some(Encoder::Deflate(enc)) => enc.finish(),
+                None => return None,
+            };
+            match res {
+                Ok(buf) => Some(buf.into()),
+                Err(err) => {
+                    error!("Can not complete compressed response: {}", err);
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "compress"))]
+mod noop {
+    use actix_web::*;
+
+    /// Streaming response encoder
+    pub struct StreamEncoder;
+
+    impl StreamEncoder {
+        pub fn negotiate<S>(_: &HttpRequest<S>, _: bool) -> StreamEncoder {
+            StreamEncoder
+        }
+
+        pub fn encoding(&self) -> Option<&'static str> {
+            None
+        }
+
+        pub(super) fn encode(&mut self, data: Binary) -> Binary {
+            data
+        }
+
+        pub fn finish(&mut self) -> Option<Binary> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use actix_web::http::header::ACCEPT_ENCODING;
+
+    use super::*;
+
+    #[test]
+    fn test_disabled() {
+        let req = TestRequest::with_header(ACCEPT_ENCODING, "gzip").finish();
+        let mut enc = StreamEncoder::negotiate(&req, false);
+        assert_eq!(enc.encoding(), None);
+        assert_eq!(enc.chunk("o\n").as_ref(), b"o\n");
+        assert!(enc.finish().is_none());
+    }
+
+    #[cfg(feature = "compress")]
+    fn decode(enc: &str, data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        use flate2::write::{GzDecoder, ZlibDecoder};
+
+        match enc {
+            "gzip" => {
+                let mut dec = GzDecoder::new(Vec::new());
+                dec.write_all(data).unwrap();
+                dec.flush().unwrap();
+                dec.get_ref().clone()
+            }
+            _ => {
+                let mut dec = ZlibDecoder::new(Vec::new());
+                dec.write_all(data).unwrap();
+                dec.flush().unwrap();
+                dec.get_ref().clone()
+            }
+        }
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn test_negotiate() {
+        let encoding = |accept: &str| {
+            let req = TestRequest::with_header(ACCEPT_ENCODING, accept).finish();
+            StreamEncoder::negotiate(&req, true).encoding()
+        };
+        assert_eq!(encoding("gzip, deflate"), Some("gzip"));
+        assert_eq!(encoding("deflate"), Some("deflate"));
+        assert_eq!(encoding("gzip;q=0, deflate;q=0.5"), Some("deflate"));
+        assert_eq!(encoding("gzip;q=0.0"), None);
+        assert_eq!(encoding("br"), None);
+
+        let req = TestRequest::default().finish();
+        assert_eq!(StreamEncoder::negotiate(&req, true).encoding(), None);
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn test_chunks_are_flushed() {
+        for name in &["gzip", "deflate"] {
+            let req = TestRequest::with_header(ACCEPT_ENCODING, *name).finish();
+            let mut enc = StreamEncoder::negotiate(&req, true);
+            assert_eq!(enc.encoding(), Some(*name));
+
+            // every chunk is decodable without waiting for the rest of stream
+            let mut data = enc.chunk("o\n").as_ref().to_vec();
+            assert_eq!(decode(name, &data), b"o\n");
+            data.extend_from_slice(enc.chunk("a[\"msg\"]\n").as_ref());
+            assert_eq!(decode(name, &data), b"o\na[\"msg\"]\n");
+
+            let tail = enc.finish().unwrap();
+            data.extend_from_slice(tail.as_ref());
+            assert_eq!(decode(name, &data), b"o\na[\"msg\"]\n");
+            assert!(enc.finish().is_none());
+        }
+    }
+}
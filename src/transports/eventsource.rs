@@ -7,12 +7,14 @@ use http::header;
 use serde_json;
 
 use context::ChannelItem;
+use spans;
 use protocol::{Frame, CloseCode};
 use utils::SockjsHeaders;
 use session::Session;
-use manager::{Broadcast, Record, SessionManager};
+use manager::{Record, SessionManager};
 
 use super::{Transport, SendResult, Flags};
+use super::encoding::StreamEncoder;
 
 
 pub struct EventSource<S, SM>
@@ -24,6 +26,7 @@ pub struct EventSource<S, SM>
     rec: Option<Record>,
     maxsize: usize,
     flags: Flags,
+    encoder: StreamEncoder,
 }
 
 impl<S, SM> EventSource<S, SM>
@@ -31,12 +34,18 @@ impl<S, SM> EventSource<S, SM>
 {
     fn hb(&self, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) {
         ctx.run_later(Duration::new(5, 0), |act, ctx| {
+            if let Some(ref rec) = act.rec {
+                spans::heartbeat(rec.span());
+            }
             act.send_heartbeat(ctx);
             act.hb(ctx);
         });
     }
 
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize) -> Result<HttpResponse>
+    /// Frames are compressed if `compress` is set and client accepts
+    /// compressed response, `maxsize` limits uncompressed size
+    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize, compress: bool)
+                -> Result<HttpResponse>
     {
         let session = req.match_info().get("session").unwrap().to_owned();
         let mut resp = HttpResponse::Ok()
@@ -46,20 +55,23 @@ impl<S, SM> EventSource<S, SM>
             .sockjs_no_cache()
             .sockjs_session_cookie(&req)
             .take();
+        let mut encoder = StreamEncoder::negotiate(&req, compress);
+        encoder.headers(&mut resp);
+        let prelude = encoder.chunk("\r\n");
 
         let mut ctx = HttpContext::new(
             req, EventSource{s: PhantomData,
                              sm: PhantomData,
                              size: 0, rec: None,
                              flags: Flags::empty(),
-                             maxsize});
-        ctx.write("\r\n");
+                             maxsize, encoder});
+        ctx.write(prelude);
 
         // init transport, but aftre prelude only
         ctx.drain().map(move |_, _, ctx| {
             ctx.run_later(Duration::new(0, 1_200_000), move |act, ctx| {
                 act.hb(ctx);
-                act.init_transport(session, ctx);
+                act.init_transport(session, None, ctx);
             });
         }).wait(&mut ctx);
 
@@ -83,46 +95,47 @@ impl<S, SM> Actor for EventSource<S, SM>
 impl<S, SM> Transport<S, SM> for EventSource<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
+    const NAME: &'static str = "eventsource";
+
     fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, rec: &mut Record)
             -> SendResult
     {
+        rec.metrics().frame_sent(Self::NAME, msg);
+        spans::frame(rec.span(), msg);
         self.size += match *msg {
             Frame::Heartbeat => {
-                ctx.write("data: h\r\n\r\n");
+                ctx.write(self.encoder.chunk("data: h\r\n\r\n"));
                 11
             },
             Frame::Message(ref s) => {
                 let blob = serde_json::to_string(&s).unwrap();
                 let size = blob.len();
-                ctx.write("data: a[");
-                ctx.write(blob);
-                ctx.write("]\r\n\r\n");
+                ctx.write(self.encoder.chunk(format!("data: a[{}]\r\n\r\n", blob)));
                 size + 13
             }
             Frame::MessageVec(ref s) => {
                 let size = s.len();
-                ctx.write("data: a");
-                ctx.write(s);
-                ctx.write("\r\n\r\n");
+                ctx.write(self.encoder.chunk(format!("data: a{}\r\n\r\n", s)));
                 size + 11
             }
             Frame::MessageBlob(_) => {
                 unimplemented!()
             }
             Frame::Open => {
-                ctx.write("data: o\r\n\r\n");
+                ctx.write(self.encoder.chunk("data: o\r\n\r\n"));
                 11
             },
-            Frame::Close(code) => {
-                rec.close();
-                ctx.write(format!("data: c[{}, {:?}]\r\n\r\n", code.num(), code.reason()));
-                ctx.write_eof();
+            Frame::Close(ref code) => {
+                rec.closed_with(code);
+                ctx.write(self.encoder.chunk(
+                    format!("data: c[{}, {:?}]\r\n\r\n", code.num(), code.reason())));
+                self.finish(ctx);
                 return SendResult::Stop
             }
         };
 
         if self.size > self.maxsize {
-            ctx.write_eof();
+            self.finish(ctx);
             SendResult::Stop
         } else {
             SendResult::Continue
@@ -131,11 +144,18 @@ impl<S, SM> Transport<S, SM> for EventSource<S, SM>
 
     fn send_close(&mut self, ctx: &mut Self::Context, code: CloseCode) {
         let blob = format!("data: c[{}, {:?}]\r\n\r\n", code.num(), code.reason());
-        ctx.write(blob);
+        ctx.write(self.encoder.chunk(blob));
+    }
+
+    fn finish(&mut self, ctx: &mut Self::Context) {
+        if let Some(tail) = self.encoder.finish() {
+            ctx.write(tail);
+        }
+        ctx.write_eof();
     }
 
     fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
-        ctx.write("data: h\r\n\r\n");
+        ctx.write(self.encoder.chunk("data: h\r\n\r\n"));
     }
 
     fn session_record(&mut self) -> &mut Option<Record> {
@@ -156,13 +176,3 @@ impl<S, SM> Handler<ChannelItem> for EventSource<S, SM>
         self.handle_message(msg, ctx)
     }
 }
-
-impl<S, SM> Handler<Broadcast> for EventSource<S, SM>
-    where S: Session, SM: SessionManager<S>,
-{
-    type Result = ();
-
-    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        self.handle_broadcast(msg, ctx)
-    }
-}
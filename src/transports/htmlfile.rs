@@ -8,12 +8,14 @@ use serde_json;
 use regex::Regex;
 
 use context::ChannelItem;
+use spans;
 use protocol::{Frame, CloseCode};
 use utils::SockjsHeaders;
 use session::Session;
-use manager::{Broadcast, Record, SessionManager};
+use manager::{Record, SessionManager};
 
 use super::{Transport, SendResult, Flags};
+use super::encoding::StreamEncoder;
 
 const PRELUDE1: &str = r#"
 <!doctype html>
@@ -43,6 +45,7 @@ pub struct HTMLFile<S, SM>
     maxsize: usize,
     rec: Option<Record>,
     flags: Flags,
+    encoder: StreamEncoder,
 }
 
 impl<S, SM> HTMLFile<S, SM>
@@ -51,6 +54,9 @@ impl<S, SM> HTMLFile<S, SM>
     // start heartbeats
     fn hb(&self, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) {
         ctx.run_later(Duration::new(5, 0), |act, ctx| {
+            if let Some(ref rec) = act.rec {
+                spans::heartbeat(rec.span());
+            }
             act.send_heartbeat(ctx);
             act.hb(ctx);
         });
@@ -59,12 +65,14 @@ impl<S, SM> HTMLFile<S, SM>
     fn write(&mut self, s: &str, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) {
         let b = serde_json::to_string(s).unwrap();
         self.size += b.len() + 25;
-        ctx.write("<script>\np(");
-        ctx.write(b);
-        ctx.write(");\n</script>\r\n");
+        ctx.write(self.encoder.chunk(format!("<script>\np({});\n</script>\r\n", b)));
     }
 
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize) -> Result<HttpResponse> {
+    /// Frames are compressed if `compress` is set and client accepts
+    /// compressed response, `maxsize` limits uncompressed size
+    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize, compress: bool)
+                -> Result<HttpResponse>
+    {
         lazy_static! {
             static ref CHECK: Regex = Regex::new(r"^[a-zA-Z0-9_\.]+$").unwrap();
         }
@@ -86,24 +94,26 @@ impl<S, SM> HTMLFile<S, SM>
                 .sockjs_no_cache()
                 .sockjs_session_cookie(&req)
                 .take();
+            let mut encoder = StreamEncoder::negotiate(&req, compress);
+            encoder.headers(&mut resp);
+            let prelude = encoder.chunk(format!("{}{}{}", PRELUDE1, callback, PRELUDE2));
+            let padding = encoder.chunk(PRELUDE3);
 
             let mut ctx = HttpContext::new(
                 req, HTMLFile{s: PhantomData,
                               sm: PhantomData,
                               size: 0, rec: None,
-                              maxsize,
+                              maxsize, encoder,
                               flags: Flags::empty()});
-            ctx.write(PRELUDE1);
-            ctx.write(callback);
-            ctx.write(PRELUDE2);
-            ctx.write(PRELUDE3);
+            ctx.write(prelude);
+            ctx.write(padding);
 
             // init transport, but aftre prelude only
             ctx.drain()
                 .map(move |_, _, ctx| {
                     ctx.run_later(Duration::new(0, 1_200_000), move |act, ctx| {
                         act.hb(ctx);
-                        act.init_transport(session, ctx);
+                        act.init_transport(session, None, ctx);
                     });
                 }).wait(&mut ctx);
 
@@ -131,9 +141,13 @@ impl<S, SM> Actor for HTMLFile<S, SM>
 impl<S, SM> Transport<S, SM> for HTMLFile<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
+    const NAME: &'static str = "htmlfile";
+
     fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, rec: &mut Record)
             -> SendResult
     {
+        rec.metrics().frame_sent(Self::NAME, msg);
+        spans::frame(rec.span(), msg);
         match *msg {
             Frame::Heartbeat => {
                 self.write("h", ctx);
@@ -151,17 +165,17 @@ impl<S, SM> Transport<S, SM> for HTMLFile<S, SM>
             Frame::Open => {
                 self.write("o", ctx);
             },
-            Frame::Close(code) => {
-                rec.close();
+            Frame::Close(ref code) => {
+                rec.closed_with(code);
                 let blob = format!("c[{},{:?}]", code.num(), code.reason());
                 self.write(&blob, ctx);
-                ctx.write_eof();
+                self.finish(ctx);
                 return SendResult::Stop
             }
         };
 
         if self.size > self.maxsize {
-            ctx.write_eof();
+            self.finish(ctx);
             SendResult::Stop
         } else {
             SendResult::Continue
@@ -172,6 +186,13 @@ impl<S, SM> Transport<S, SM> for HTMLFile<S, SM>
         self.write(&format!("c[{},{:?}]", code.num(), code.reason()), ctx);
     }
 
+    fn finish(&mut self, ctx: &mut Self::Context) {
+        if let Some(tail) = self.encoder.finish() {
+            ctx.write(tail);
+        }
+        ctx.write_eof();
+    }
+
     fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
         self.write("h", ctx);
     }
@@ -194,18 +215,3 @@ impl<S, SM> Handler<ChannelItem> for HTMLFile<S, SM>
         self.handle_message(msg, ctx)
     }
 }
-
-impl<S, SM> Handler<Broadcast> for HTMLFile<S, SM>
-    where S: Session, SM: SessionManager<S>
-{
-    type Result = ();
-
-    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        if let Some(mut rec) = self.rec.take() {
-            self.send(ctx, &msg.msg, &mut rec);
-            self.rec = Some(rec);
-        } else if let Some(ref mut rec) = self.rec {
-            rec.add(msg);
-        }
-    }
-}
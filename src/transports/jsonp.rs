@@ -12,12 +12,13 @@ use regex::Regex;
 use serde_json;
 
 use context::ChannelItem;
-use manager::{Broadcast, Record, SessionManager, SessionMessage};
+use spans;
+use manager::{Record, SessionManager, SessionMessage};
 use protocol::{CloseCode, Frame};
 use session::{Message, Session};
 use utils::SockjsHeaders;
 
-use super::{Flags, SendResult, Transport, MAXSIZE};
+use super::{ack_param, Flags, SendResult, Transport, MAXSIZE};
 
 pub struct JSONPolling<S, SM>
 where
@@ -65,7 +66,11 @@ where
     S: Session,
     SM: SessionManager<S>,
 {
+    const NAME: &'static str = "jsonp";
+
     fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, record: &mut Record) -> SendResult {
+        record.metrics().frame_sent(Self::NAME, msg);
+        spans::frame(record.span(), msg);
         match *msg {
             Frame::Heartbeat => {
                 self.write("h", ctx);
@@ -80,8 +85,8 @@ where
             Frame::Open => {
                 self.write("o", ctx);
             }
-            Frame::Close(code) => {
-                record.close();
+            Frame::Close(ref code) => {
+                record.closed_with(code);
                 let blob = format!("c[{},{:?}]", code.num(), code.reason());
                 self.write(&blob, ctx);
             }
@@ -129,6 +134,7 @@ where
             }
 
             let session = req.match_info().get("session").unwrap().to_owned();
+            let ack = ack_param(&req);
             let mut resp = HttpResponse::Ok()
                 .content_type("application/javascript; charset=UTF-8")
                 .force_close()
@@ -146,7 +152,7 @@ where
                 callback,
             };
             // init transport
-            transport.init_transport(session, &mut ctx);
+            transport.init_transport(session, ack, &mut ctx);
 
             Ok(resp.body(ctx.actor(transport)))
         } else {
@@ -167,18 +173,6 @@ where
     }
 }
 
-impl<S, SM> Handler<Broadcast> for JSONPolling<S, SM>
-where
-    S: Session,
-    SM: SessionManager<S>,
-{
-    type Result = ();
-
-    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        self.handle_broadcast(msg, ctx)
-    }
-}
-
 #[allow(non_snake_case)]
 pub fn JSONPollingSend<S, SM>(
     req: HttpRequest<Addr<Syn, SM>>,
@@ -266,6 +260,7 @@ where
                             req.state().do_send(SessionMessage {
                                 sid: Arc::clone(&sid),
                                 msg: Message(msg),
+                                transport: "jsonp_send",
                             });
                         }
 
@@ -274,6 +269,7 @@ where
                                 .send(SessionMessage {
                                     sid: Arc::clone(&sid),
                                     msg: Message(last),
+                                    transport: "jsonp_send",
                                 })
                                 .from_err()
                                 .and_then(move |res| match res {
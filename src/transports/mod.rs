@@ -4,8 +4,10 @@ use actix_web::*;
 use context::ChannelItem;
 use protocol::{Frame, CloseCode};
 use session::{Session, SessionState};
-use manager::{Acquire, Release, Broadcast, Record, SessionManager};
+use manager::{Acquire, Release, Record, SessionManager};
 
+mod deflate;
+mod encoding;
 mod xhr;
 mod xhrsend;
 mod xhrstreaming;
@@ -23,6 +25,7 @@ pub use self::htmlfile::HTMLFile;
 pub use self::websocket::Websocket;
 pub use self::rawwebsocket::RawWebsocket;
 pub use self::jsonp::{JSONPolling, JSONPollingSend};
+pub use self::deflate::DeflateConfig;
 
 pub const MAXSIZE: usize = 131_072;  // 128K bytes
 
@@ -35,6 +38,12 @@ bitflags! {
 
 type TransportContext<T, SM> = HttpContext<T, Addr<Syn, SM>>;
 
+/// Sequence number of last message frame received by peer,
+/// `ack` query parameter
+fn ack_param<S>(req: &HttpRequest<S>) -> Option<u64> {
+    req.query().get("ack").and_then(|s| s.parse().ok())
+}
+
 /// Result of `Transport::send` method
 #[derive(PartialEq)]
 pub enum SendResult {
@@ -45,9 +54,12 @@ pub enum SendResult {
 }
 
 trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
-    Handler<ChannelItem> + Handler<Broadcast>
+    Handler<ChannelItem>
     where S: Session, SM: SessionManager<S>,
 {
+    /// Transport name
+    const NAME: &'static str;
+
     /// Session flags
     fn flags(&mut self) -> &mut Flags;
 
@@ -65,29 +77,17 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
         ctx.stop();
     }
 
-    fn handle_broadcast(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        if let Some(mut rec) = self.session_record().take() {
-            if self.flags().contains(Flags::READY) {
-                rec.add(msg.msg);
-                *self.session_record() = Some(rec);
-            } else if SendResult::Stop == self.send(ctx, &msg.msg, &mut rec) {
-                *self.session_record() = Some(rec);
-                self.release(ctx);
-            } else {
-                *self.session_record() = Some(rec);
-            }
-        }
-    }
-
     fn handle_message(&mut self, msg: ChannelItem, ctx: &mut Self::Context) {
         match msg {
             ChannelItem::Frame(msg) => {
                 if let Some(mut rec) = self.session_record().take() {
                     if self.flags().contains(Flags::READY) {
                         if SendResult::Stop == self.send(ctx, &msg, &mut rec) {
+                            rec.sent(msg);
                             *self.session_record() = Some(rec);
                             self.release(ctx);
                         } else {
+                            rec.sent(msg);
                             *self.session_record() = Some(rec);
                         }
                     } else {
@@ -124,12 +124,19 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
     /// Send heartbeat
     fn send_heartbeat(&mut self, ctx: &mut TransportContext<Self, SM>);
 
+    /// Complete response
+    fn finish(&mut self, ctx: &mut TransportContext<Self, SM>) {
+        ctx.write_eof();
+    }
+
     /// Send sockjs frame
     fn send_buffered(&mut self, ctx: &mut TransportContext<Self, SM>, record: &mut Record)
                      -> SendResult {
         while !record.buffer.is_empty() {
             if let Some(msg) = record.buffer.pop_front() {
-                if let SendResult::Stop = self.send(ctx, msg.as_ref(), record) {
+                let res = self.send(ctx, msg.as_ref(), record);
+                record.sent(msg);
+                if let SendResult::Stop = res {
                     return SendResult::Stop
                 }
             }
@@ -137,10 +144,11 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
         SendResult::Continue
     }
 
-    fn init_transport(&mut self, session: String, ctx: &mut TransportContext<Self, SM>) {
+    /// Acquire session, `ack` is reported by polling transports only
+    fn init_transport(&mut self, session: String, ack: Option<u64>,
+                      ctx: &mut TransportContext<Self, SM>) {
         // acquire session
-        let addr: Addr<Syn, _> = ctx.address();
-        ctx.state().send(Acquire::new(session, addr.recipient()))
+        ctx.state().send(Acquire::new(session, Self::NAME).ack(ack))
             .into_actor(self)
             .map(|res, act, ctx| {
                 match res {
@@ -158,7 +166,7 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
                                 ctx.add_message_stream(rec.1);
                             },
                             SessionState::New => {
-                                rec.0.state = SessionState::Running;
+                                rec.0.set_state(SessionState::Running);
                                 if let SendResult::Stop = act.send(ctx, &Frame::Open, &mut rec.0)
                                 {
                                     // release is send stops
@@ -179,21 +187,22 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
                             },
 
                             SessionState::Closed => {
-                                act.send(ctx, &Frame::Close(CloseCode::GoAway), &mut rec.0);
+                                let code = rec.0.close_code();
+                                act.send(ctx, &Frame::Close(code), &mut rec.0);
                                 ctx.state().do_send(Release{ses: rec.0});
                             }
                         }
                     },
                     Err(err) => {
                         act.send_close(ctx, err.into());
-                        ctx.write_eof();
+                        act.finish(ctx);
                     }
                 }
             })
             // session manager is dead?
             .map_err(|_, act, ctx| {
                 act.send_close(ctx, CloseCode::InternalError);
-                ctx.write_eof();
+                act.finish(ctx);
             })
             .wait(ctx);
     }
@@ -3,15 +3,29 @@ use std::sync::Arc;
 
 use actix::*;
 use actix_web::*;
-use rand;
 
 use context::ChannelItem;
-use manager::{Acquire, Broadcast, Record, Release, SessionManager, SessionMessage};
+use spans;
+use manager::{Acquire, Record, Release, SessionManager, SessionMessage};
 use protocol::{CloseCode, Frame};
 use session::{Message, Session, SessionState};
 
+use super::deflate::{self, Deflater, DeflateConfig};
 use super::{Flags, SendResult};
 
+/// Websocket close reason for sockjs close code
+fn close_reason(code: &CloseCode) -> ws::CloseReason {
+    let num = match *code {
+        CloseCode::Interrupted | CloseCode::InternalError => ws::CloseCode::Error,
+        CloseCode::Acquired | CloseCode::GoAway => ws::CloseCode::Away,
+        CloseCode::Custom(num, _) => ws::CloseCode::Other(num),
+    };
+    ws::CloseReason {
+        code: num,
+        description: Some(code.reason().to_owned()),
+    }
+}
+
 pub struct RawWebsocket<S, SM>
 where
     S: Session,
@@ -21,6 +35,7 @@ where
     sm: PhantomData<SM>,
     rec: Option<Record>,
     flags: Flags,
+    deflate: Option<Deflater>,
 }
 
 impl<S, SM> RawWebsocket<S, SM>
@@ -28,20 +43,20 @@ where
     S: Session,
     SM: SessionManager<S>,
 {
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>) -> Result<HttpResponse> {
+    pub fn init(req: HttpRequest<Addr<Syn, SM>>, sid: String, deflate: Option<&DeflateConfig>)
+                -> Result<HttpResponse>
+    {
         let mut resp = ws::handshake(&req)?;
 
-        // session
-        let sid = format!("{}", rand::random::<u32>());
-
         let mut ctx = ws::WebsocketContext::from_request(req.clone());
-        ctx.add_stream(ws::WsStream::new(req));
+        let deflate = deflate::start(&req, &mut resp, &mut ctx, deflate);
 
         let mut tr = RawWebsocket {
             s: PhantomData,
             sm: PhantomData,
             rec: None,
             flags: Flags::empty(),
+            deflate,
         };
         // init transport
         tr.init_transport(sid, &mut ctx);
@@ -55,34 +70,30 @@ where
         msg: &Frame,
         record: &mut Record,
     ) -> SendResult {
+        record.metrics().frame_sent("rawwebsocket", msg);
+        spans::frame(record.span(), msg);
         match *msg {
             Frame::Heartbeat => {
                 ctx.ping("");
             }
             Frame::Message(ref s) | Frame::MessageVec(ref s) => {
-                ctx.text(s);
+                deflate::text(ctx, &mut self.deflate, s.as_str());
             }
             Frame::MessageBlob(ref b) => {
                 ctx.binary(b.clone());
             }
             Frame::Open => (),
-            Frame::Close(_) => {
-                record.close();
-                ctx.close(Some(ws::CloseReason {
-                    code: ws::CloseCode::Normal,
-                    description: Some("Go away!".to_owned()),
-                }));
+            Frame::Close(ref code) => {
+                record.closed_with(code);
+                ctx.close(Some(close_reason(code)));
             }
         };
 
         SendResult::Continue
     }
 
-    fn send_close(&mut self, ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>, _: CloseCode) {
-        ctx.close(Some(ws::CloseReason {
-            code: ws::CloseCode::Normal,
-            description: Some("Go away!".to_owned()),
-        }));
+    fn send_close(&mut self, ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>, code: CloseCode) {
+        ctx.close(Some(close_reason(&code)));
     }
 
     fn session_record(&mut self) -> &mut Option<Record> {
@@ -165,8 +176,7 @@ where
         ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
     ) {
         // acquire session
-        let addr: Addr<Syn, _> = ctx.address();
-        ctx.state().send(Acquire::new(session, addr.recipient()))
+        ctx.state().send(Acquire::new(session, "rawwebsocket"))
             .into_actor(self)
             .map(|res, act, ctx| {
                 match res {
@@ -184,7 +194,7 @@ where
                                 ctx.add_message_stream(rec.1);
                             },
                             SessionState::New => {
-                                rec.0.state = SessionState::Running;
+                                rec.0.set_state(SessionState::Running);
                                 if let SendResult::Stop = act.send(ctx, &Frame::Open, &mut rec.0)
                                 {
                                     // release is send stops
@@ -205,7 +215,8 @@ where
                             },
 
                             SessionState::Closed => {
-                                act.send(ctx, &Frame::Close(CloseCode::GoAway), &mut rec.0);
+                                let code = rec.0.close_code();
+                                act.send(ctx, &Frame::Close(code), &mut rec.0);
                                 ctx.state().do_send(Release{ses: rec.0});
                             }
                         }
@@ -253,28 +264,14 @@ where
     }
 }
 
-impl<S, SM> Handler<Broadcast> for RawWebsocket<S, SM>
-where
-    S: Session,
-    SM: SessionManager<S>,
-{
-    type Result = ();
-
-    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        if let Some(mut rec) = self.rec.take() {
-            self.send(ctx, &msg.msg, &mut rec);
-            self.rec = Some(rec);
-        }
-    }
-}
-
 impl<S, SM> StreamHandler<ws::Message, ws::ProtocolError> for RawWebsocket<S, SM>
 where
     S: Session,
     SM: SessionManager<S>,
 {
-    fn error(&mut self, _: ws::ProtocolError, ctx: &mut Self::Context) -> Running {
-        if let Some(rec) = self.rec.take() {
+    fn error(&mut self, err: ws::ProtocolError, ctx: &mut Self::Context) -> Running {
+        if let Some(mut rec) = self.rec.take() {
+            rec.protocol_error(format!("{}", err));
             ctx.state().do_send(Release { ses: rec });
         }
         self.release(ctx);
@@ -288,17 +285,46 @@ where
             ws::Message::Text(text) => {
                 if !text.is_empty() {
                     if let Some(ref rec) = self.rec {
-                        ctx.state().do_send(SessionMessage {
-                            sid: Arc::clone(&rec.sid),
-                            msg: Message(text),
-                        });
+                        let len = text.len();
+                        match rec.deliver(Message(text)) {
+                            Ok(()) => rec.metrics().frames_received("rawwebsocket", 1, len),
+                            Err(msg) => ctx.state().do_send(SessionMessage {
+                                sid: Arc::clone(&rec.sid), msg, transport: "rawwebsocket",
+                            }),
+                        }
                     }
                 }
             }
             ws::Message::Binary(_) => {
                 error!("Not supported!");
             }
+            ws::Message::Close(reason) => {
+                if let Some(mut rec) = self.rec.take() {
+                    match reason {
+                        Some(reason) => rec.peer_closed(Some(reason.code.into()), reason.description),
+                        None => rec.peer_closed(None, None),
+                    }
+                    ctx.state().do_send(Release { ses: rec });
+                }
+                ctx.stop();
+            }
             _ => (),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_reason() {
+        let code = |code: CloseCode| -> u16 { close_reason(&code).code.into() };
+        assert_eq!(code(CloseCode::Interrupted), 1011);
+        assert_eq!(code(CloseCode::Acquired), 1001);
+        assert_eq!(code(CloseCode::GoAway), 1001);
+        assert_eq!(code(CloseCode::Custom(4000, "Bye".to_owned())), 4000);
+        assert_eq!(close_reason(&CloseCode::Acquired).description,
+                   Some("Another connection still open".to_owned()));
+    }
+}
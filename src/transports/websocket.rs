@@ -6,10 +6,12 @@ use actix_web::*;
 use serde_json;
 
 use context::ChannelItem;
-use manager::{Acquire, Broadcast, Record, Release, SessionManager, SessionMessage};
+use spans;
+use manager::{Acquire, Record, Release, SessionManager, SessionMessage};
 use protocol::{CloseCode, Frame};
 use session::{Message, Session, SessionState};
 
+use super::deflate::{self, Deflater, DeflateConfig};
 use super::{Flags, SendResult};
 
 pub struct Websocket<S, SM>
@@ -21,6 +23,7 @@ where
     sm: PhantomData<SM>,
     rec: Option<Record>,
     flags: Flags,
+    deflate: Option<Deflater>,
 }
 
 impl<S, SM> Websocket<S, SM>
@@ -28,12 +31,14 @@ where
     S: Session,
     SM: SessionManager<S>,
 {
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>) -> Result<HttpResponse> {
+    pub fn init(req: HttpRequest<Addr<Syn, SM>>, deflate: Option<&DeflateConfig>)
+                -> Result<HttpResponse>
+    {
         let mut resp = ws::handshake(&req)?;
         let session = req.match_info().get("session").unwrap().to_owned();
 
         let mut ctx = ws::WebsocketContext::from_request(req.clone());
-        ctx.add_stream(ws::WsStream::new(req));
+        let deflate = deflate::start(&req, &mut resp, &mut ctx, deflate);
 
         // init transport
         let mut tr = Websocket {
@@ -41,6 +46,7 @@ where
             sm: PhantomData,
             rec: None,
             flags: Flags::empty(),
+            deflate,
         };
         tr.init_transport(session, &mut ctx);
 
@@ -53,24 +59,26 @@ where
         msg: &Frame,
         record: &mut Record,
     ) -> SendResult {
+        record.metrics().frame_sent("websocket", msg);
+        spans::frame(record.span(), msg);
         match *msg {
             Frame::Heartbeat => {
-                ctx.text("h");
+                deflate::text(ctx, &mut self.deflate, "h");
             }
             Frame::Message(ref s) => {
-                ctx.text(format!("a[{:?}]", s));
+                deflate::text(ctx, &mut self.deflate, format!("a[{:?}]", s));
             }
             Frame::MessageVec(ref s) => {
-                ctx.text(format!("a{}", s));
+                deflate::text(ctx, &mut self.deflate, format!("a{}", s));
             }
             Frame::MessageBlob(_) => {
                 // ctx.write(format!("a{}\n", s));
             }
             Frame::Open => {
-                ctx.text("o");
+                deflate::text(ctx, &mut self.deflate, "o");
             }
-            Frame::Close(code) => {
-                record.close();
+            Frame::Close(ref code) => {
+                record.closed_with(code);
                 ctx.text(format!("c[{},{:?}]\n", code.num(), code.reason()));
             }
         };
@@ -162,8 +170,7 @@ where
         ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
     ) {
         // acquire session
-        let addr: Addr<Syn, _> = ctx.address();
-        ctx.state().send(Acquire::new(session, addr.recipient()))
+        ctx.state().send(Acquire::new(session, "websocket"))
             .into_actor(self)
             .map(|res, act, ctx| {
                 match res {
@@ -181,7 +188,7 @@ where
                                 ctx.add_message_stream(rec.1);
                             },
                             SessionState::New => {
-                                rec.0.state = SessionState::Running;
+                                rec.0.set_state(SessionState::Running);
                                 if let SendResult::Stop = act.send(ctx, &Frame::Open, &mut rec.0)
                                 {
                                     // release is send stops
@@ -202,7 +209,8 @@ where
                             },
 
                             SessionState::Closed => {
-                                act.send(ctx, &Frame::Close(CloseCode::GoAway), &mut rec.0);
+                                let code = rec.0.close_code();
+                                act.send(ctx, &Frame::Close(code), &mut rec.0);
                                 ctx.state().do_send(Release{ses: rec.0});
                             }
                         }
@@ -250,29 +258,14 @@ where
     }
 }
 
-impl<S, SM> Handler<Broadcast> for Websocket<S, SM>
-where
-    S: Session,
-    SM: SessionManager<S>,
-{
-    type Result = ();
-
-    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        if let Some(mut rec) = self.rec.take() {
-            self.send(ctx, &msg.msg, &mut rec);
-            self.rec = Some(rec);
-        }
-    }
-}
-
 impl<S, SM> StreamHandler<ws::Message, ws::ProtocolError> for Websocket<S, SM>
 where
     S: Session,
     SM: SessionManager<S>,
 {
-    fn error(&mut self, _: ws::ProtocolError, ctx: &mut Self::Context) -> Running {
+    fn error(&mut self, err: ws::ProtocolError, ctx: &mut Self::Context) -> Running {
         if let Some(mut rec) = self.rec.take() {
-            rec.interrupted();
+            rec.protocol_error(format!("{}", err));
             ctx.state().do_send(Release { ses: rec });
         }
         Running::Stop
@@ -298,7 +291,7 @@ where
                                 description: Some("Broken JSON encoding".to_owned()),
                             }));
                             if let Some(mut rec) = self.rec.take() {
-                                rec.interrupted();
+                                rec.protocol_error("Broken JSON encoding");
                                 ctx.state().do_send(Release { ses: rec });
                             }
                             ctx.stop();
@@ -314,7 +307,7 @@ where
                                 description: Some("Broken JSON encoding".to_owned()),
                             }));
                             if let Some(mut rec) = self.rec.take() {
-                                rec.interrupted();
+                                rec.protocol_error("Broken JSON encoding");
                                 ctx.state().do_send(Release { ses: rec });
                             }
                             ctx.stop();
@@ -322,20 +315,25 @@ where
                         }
                     }
                 };
-
                 if let Some(ref rec) = self.rec {
-                    ctx.state().do_send(SessionMessage {
-                        sid: Arc::clone(&rec.sid),
-                        msg: Message(msg),
-                    });
+                    let len = msg.len();
+                    match rec.deliver(Message(msg)) {
+                        Ok(()) => rec.metrics().frames_received("websocket", 1, len),
+                        Err(msg) => ctx.state().do_send(SessionMessage {
+                            sid: Arc::clone(&rec.sid), msg, transport: "websocket",
+                        }),
+                    }
                 }
             }
             ws::Message::Binary(_) => {
                 error!("Binary messages are not supported");
             }
-            ws::Message::Close(_) => {
+            ws::Message::Close(reason) => {
                 if let Some(mut rec) = self.rec.take() {
-                    rec.close();
+                    match reason {
+                        Some(reason) => rec.peer_closed(Some(reason.code.into()), reason.description),
+                        None => rec.peer_closed(None, None),
+                    }
                     ctx.state().do_send(Release { ses: rec });
                 }
                 ctx.stop();
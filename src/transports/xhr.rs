@@ -7,12 +7,13 @@ use serde_json;
 use http::header::{self, ACCESS_CONTROL_ALLOW_METHODS};
 
 use context::ChannelItem;
+use spans;
 use protocol::{Frame, CloseCode};
 use utils::SockjsHeaders;
 use session::Session;
-use manager::{Broadcast, Record, SessionManager};
+use manager::{Record, SessionManager};
 
-use super::{Transport, SendResult, Flags};
+use super::{ack_param, Transport, SendResult, Flags};
 
 
 pub struct Xhr<S, SM>
@@ -40,8 +41,12 @@ impl<S, SM> Actor for Xhr<S, SM>
 impl<S, SM> Transport<S, SM> for Xhr<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
+    const NAME: &'static str = "xhr";
+
     fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, record: &mut Record) -> SendResult
     {
+        record.metrics().frame_sent(Self::NAME, msg);
+        spans::frame(record.span(), msg);
         match *msg {
             Frame::Heartbeat => {
                 ctx.write("h\n");
@@ -62,8 +67,8 @@ impl<S, SM> Transport<S, SM> for Xhr<S, SM>
             Frame::Open => {
                 ctx.write("o\n");
             },
-            Frame::Close(code) => {
-                record.close();
+            Frame::Close(ref code) => {
+                record.closed_with(code);
                 let blob = format!("c[{},{:?}]\n", code.num(), code.reason());
                 ctx.write(blob);
             }
@@ -112,6 +117,7 @@ impl<S, SM> Xhr<S, SM>
         }
 
         let session = req.match_info().get("session").unwrap().to_owned();
+        let ack = ack_param(&req);
         let mut resp = HttpResponse::Ok()
             .header(header::CONTENT_TYPE, "application/javascript; charset=UTF-8")
             .force_close()
@@ -127,7 +133,7 @@ impl<S, SM> Xhr<S, SM>
                                 sm: PhantomData,
                                 rec: None,
                                 flags: Flags::empty()};
-        transport.init_transport(session, &mut ctx);
+        transport.init_transport(session, ack, &mut ctx);
 
         Ok(resp.body(ctx.actor(transport)))
     }
@@ -142,13 +148,3 @@ impl<S, SM> Handler<ChannelItem> for Xhr<S, SM>
         self.handle_message(msg, ctx)
     }
 }
-
-impl<S, SM> Handler<Broadcast> for Xhr<S, SM>
-    where S: Session, SM: SessionManager<S>,
-{
-    type Result = ();
-
-    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        self.handle_broadcast(msg, ctx)
-    }
-}
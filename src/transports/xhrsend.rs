@@ -81,6 +81,7 @@ where
                             req.state().do_send(SessionMessage {
                                 sid: Arc::clone(&sid),
                                 msg: Message(msg),
+                                transport: "xhr_send",
                             });
                         }
 
@@ -89,6 +90,7 @@ where
                                 .send(SessionMessage {
                                     sid: Arc::clone(&sid),
                                     msg: Message(last),
+                                    transport: "xhr_send",
                                 })
                                 .from_err()
                                 .and_then(move |res| match res {
@@ -7,12 +7,14 @@ use actix_web::http::Method;
 use actix_web::http::header::ACCESS_CONTROL_ALLOW_METHODS;
 
 use context::ChannelItem;
+use spans;
 use protocol::{Frame, CloseCode};
 use utils::SockjsHeaders;
 use session::Session;
-use manager::{Broadcast, Record, SessionManager};
+use manager::{Record, SessionManager};
 
 use super::{Transport, SendResult, Flags};
+use super::encoding::StreamEncoder;
 
 
 const OPEN_SEQ: &str =
@@ -56,11 +58,16 @@ pub struct XhrStreaming<S, SM>
     maxsize: usize,
     flags: Flags,
     rec: Option<Record>,
+    encoder: StreamEncoder,
 }
 
 impl<S, SM> XhrStreaming<S, SM> where S: Session, SM: SessionManager<S> {
 
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize) -> Result<HttpResponse> {
+    /// Frames are compressed if `compress` is set and client accepts
+    /// compressed response, `maxsize` limits uncompressed size
+    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize, compress: bool)
+                -> Result<HttpResponse>
+    {
         if *req.method() == Method::OPTIONS {
             return Ok(
                 HttpResponse::NoContent()
@@ -82,20 +89,24 @@ impl<S, SM> XhrStreaming<S, SM> where S: Session, SM: SessionManager<S> {
             .sockjs_session_cookie(&req)
             .sockjs_cors_headers(req.headers())
             .take();
+        let mut encoder = StreamEncoder::negotiate(&req, compress);
+        encoder.headers(&mut resp);
+        // prelude is compressed like the rest of the stream
+        let prelude = encoder.chunk(OPEN_SEQ);
 
         let mut ctx = HttpContext::new(
-            req, XhrStreaming{maxsize,
+            req, XhrStreaming{maxsize, encoder,
                               s: PhantomData,
                               sm: PhantomData,
                               size: 0,
                               flags: Flags::empty(),
                               rec: None});
-        ctx.write(OPEN_SEQ);
+        ctx.write(prelude);
 
         // init transport, but aftre prelude only
         ctx.drain().map(move |_, _, ctx| {
             ctx.run_later(Duration::new(0, 1_200_000), move |act, ctx| {
-                act.init_transport(session, ctx);
+                act.init_transport(session, None, ctx);
             });
         }).wait(&mut ctx);
 
@@ -119,25 +130,29 @@ impl<S, SM> Actor for XhrStreaming<S, SM>
 impl<S, SM> Transport<S, SM> for XhrStreaming<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
+    const NAME: &'static str = "xhr_streaming";
+
     fn send(&mut self,
             ctx: &mut Self::Context,
             msg: &Frame, record: &mut Record) -> SendResult
     {
+        record.metrics().frame_sent(Self::NAME, msg);
+        spans::frame(record.span(), msg);
         self.size += match *msg {
             Frame::Heartbeat => {
-                ctx.write("h\n");
+                ctx.write(self.encoder.chunk("h\n"));
                 2
             },
             Frame::Message(ref s) => {
                 let s = format!("a[{:?}]\n", s);
                 let size = s.len();
-                ctx.write(s);
+                ctx.write(self.encoder.chunk(s));
                 size
             }
             Frame::MessageVec(ref s) => {
                 let s = format!("a{}\n", s);
                 let size = s.len();
-                ctx.write(s);
+                ctx.write(self.encoder.chunk(s));
                 size
             }
             Frame::MessageBlob(_) => {
@@ -145,31 +160,39 @@ impl<S, SM> Transport<S, SM> for XhrStreaming<S, SM>
                 0
             }
             Frame::Open => {
-                ctx.write("o\n");
+                ctx.write(self.encoder.chunk("o\n"));
                 2
             },
-            Frame::Close(code) => {
-                record.close();
-                ctx.write(format!("c[{},{:?}]\n", code.num(), code.reason()));
-                ctx.write_eof();
+            Frame::Close(ref code) => {
+                record.closed_with(code);
+                ctx.write(self.encoder.chunk(
+                    format!("c[{},{:?}]\n", code.num(), code.reason())));
+                self.finish(ctx);
                 return SendResult::Stop;
             }
         };
 
         if self.size > self.maxsize {
-            ctx.write_eof();
+            self.finish(ctx);
             SendResult::Stop
         } else {
             SendResult::Continue
         }
     }
 
+    fn finish(&mut self, ctx: &mut Self::Context) {
+        if let Some(tail) = self.encoder.finish() {
+            ctx.write(tail);
+        }
+        ctx.write_eof();
+    }
+
     fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
-        ctx.write("h\n");
+        ctx.write(self.encoder.chunk("h\n"));
     }
 
     fn send_close(&mut self, ctx: &mut Self::Context, code: CloseCode) {
-        ctx.write(format!("c[{},{:?}]\n", code.num(), code.reason()));
+        ctx.write(self.encoder.chunk(format!("c[{},{:?}]\n", code.num(), code.reason())));
     }
 
     fn session_record(&mut self) -> &mut Option<Record> {
@@ -190,13 +213,3 @@ impl<S, SM> Handler<ChannelItem> for XhrStreaming<S, SM>
         self.handle_message(msg, ctx)
     }
 }
-
-impl<S, SM> Handler<Broadcast> for XhrStreaming<S, SM>
-    where S: Session, SM: SessionManager<S>,
-{
-    type Result = ();
-
-    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        self.handle_broadcast(msg, ctx)
-    }
-}
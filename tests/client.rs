@@ -0,0 +1,152 @@
+//! Client against in-process server
+extern crate actix;
+extern crate actix_web;
+extern crate futures;
+extern crate sockjs;
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::test::TestServer;
+use actix_web::*;
+use futures::{future, Stream};
+
+use sockjs::{ClientEvent, ClientReader, ClientTransport, ClientWriter, CloseCode, SockJSClient};
+
+mod common;
+
+use common::server;
+
+type Opened = Arc<Mutex<HashSet<String>>>;
+
+/// Server that answers only first polling request of every session,
+/// so client loses connection right after open frame
+fn flaky_server() -> TestServer {
+    let opened = Opened::default();
+    TestServer::with_factory(move || {
+        App::with_state(Arc::clone(&opened))
+            .resource("/flaky/info", |r| r.f(|_| {
+                HttpResponse::Ok()
+                    .content_type("application/json; charset=UTF-8")
+                    .body(r#"{"websocket":false}"#)
+            }))
+            .resource("/flaky/{server}/{session}/xhr", |r| r.f(flaky_xhr))
+    })
+}
+
+fn flaky_xhr(req: HttpRequest<Opened>) -> HttpResponse {
+    let session = req.match_info().get("session").unwrap().to_owned();
+    if req.state().lock().unwrap().insert(session) {
+        HttpResponse::Ok().body("o\n")
+    } else {
+        HttpResponse::InternalServerError().finish()
+    }
+}
+
+fn connect(srv: &mut TestServer, client: SockJSClient) -> (ClientReader, ClientWriter) {
+    srv.execute(future::lazy(move || Ok::<_, ()>(client.connect()))).unwrap()
+}
+
+fn next(srv: &mut TestServer, reader: ClientReader) -> (Option<ClientEvent>, ClientReader) {
+    srv.execute(reader.into_future()).map_err(|_| ()).unwrap()
+}
+
+fn echo(transport: ClientTransport) {
+    let mut srv = server();
+    let client = SockJSClient::new(srv.url("/echo")).transports(vec![transport]);
+    let (reader, writer) = connect(&mut srv, client);
+
+    // queued until session is opened
+    writer.send("first");
+
+    let (ev, reader) = next(&mut srv, reader);
+    assert_eq!(ev, Some(ClientEvent::Open(transport)));
+    let (ev, reader) = next(&mut srv, reader);
+    assert_eq!(ev, Some(ClientEvent::Message("first".to_owned())));
+
+    // messages are delivered in order
+    for i in 0..10 {
+        writer.send(format!("message {} \"quoted\" \u{e9}", i));
+    }
+    let mut reader = reader;
+    for i in 0..10 {
+        let (ev, r) = next(&mut srv, reader);
+        assert_eq!(ev, Some(ClientEvent::Message(format!("message {} \"quoted\" \u{e9}", i))));
+        reader = r;
+    }
+
+    writer.close();
+    let (ev, _) = next(&mut srv, reader);
+    assert_eq!(ev, None);
+}
+
+#[test]
+fn test_websocket() {
+    echo(ClientTransport::Websocket);
+}
+
+#[test]
+fn test_xhr_streaming() {
+    echo(ClientTransport::XhrStreaming);
+}
+
+#[test]
+fn test_xhr_polling() {
+    echo(ClientTransport::XhrPolling);
+}
+
+#[test]
+fn test_fallback() {
+    let mut srv = server();
+    let client = SockJSClient::new(srv.url("/disabled_websocket_echo"));
+    let (reader, _writer) = connect(&mut srv, client);
+
+    let (ev, _) = next(&mut srv, reader);
+    assert_eq!(ev, Some(ClientEvent::Open(ClientTransport::XhrStreaming)));
+}
+
+#[test]
+fn test_close() {
+    let mut srv = server();
+    for transport in &[ClientTransport::Websocket,
+                       ClientTransport::XhrStreaming,
+                       ClientTransport::XhrPolling]
+    {
+        let client = SockJSClient::new(srv.url("/close")).transports(vec![*transport]);
+        let (reader, _writer) = connect(&mut srv, client);
+
+        let (ev, reader) = next(&mut srv, reader);
+        assert_eq!(ev, Some(ClientEvent::Open(*transport)));
+        let (ev, reader) = next(&mut srv, reader);
+        assert_eq!(ev, Some(ClientEvent::Close(CloseCode::GoAway)));
+        let (ev, _) = next(&mut srv, reader);
+        assert_eq!(ev, None);
+    }
+}
+
+#[test]
+fn test_no_server() {
+    let mut srv = server();
+    let client = SockJSClient::new(srv.url("/unknown"));
+    let (reader, _writer) = connect(&mut srv, client);
+
+    assert!(srv.execute(reader.into_future()).is_err());
+}
+
+#[test]
+fn test_reconnect() {
+    let mut srv = flaky_server();
+    let client = SockJSClient::new(srv.url("/flaky"))
+        .transports(vec![ClientTransport::XhrPolling])
+        .reconnect(1, Duration::from_millis(10));
+    let (reader, _writer) = connect(&mut srv, client);
+
+    let (ev, reader) = next(&mut srv, reader);
+    assert_eq!(ev, Some(ClientEvent::Open(ClientTransport::XhrPolling)));
+    let (ev, reader) = next(&mut srv, reader);
+    assert_eq!(ev, Some(ClientEvent::Reconnecting));
+    // new session
+    let (ev, _) = next(&mut srv, reader);
+    assert_eq!(ev, Some(ClientEvent::Open(ClientTransport::XhrPolling)));
+}
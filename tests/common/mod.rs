@@ -0,0 +1,85 @@
+//! Sessions and server shared by integration tests
+#![allow(dead_code)]
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::test::TestServer;
+use actix_web::*;
+
+use sockjs::{Message, Session, ShardedManager, SockJS, SockJSContext, SockJSManager};
+
+#[derive(Default)]
+pub struct Echo;
+
+impl Actor for Echo {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Echo {}
+
+impl Handler<Message> for Echo {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.send(msg);
+    }
+}
+
+#[derive(Default)]
+pub struct Close;
+
+impl Actor for Close {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Close {
+    fn opened(&mut self, ctx: &mut SockJSContext<Self>) {
+        ctx.close()
+    }
+}
+
+impl Handler<Message> for Close {
+    type Result = ();
+
+    fn handle(&mut self, _: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.close()
+    }
+}
+
+pub type Managers = (Addr<Syn, SockJSManager<Echo>>,
+                 Addr<Syn, SockJSManager<Close>>,
+                 Addr<Syn, SockJSManager<Echo>>,
+                 ShardedManager<Echo>);
+
+/// Managers live in their own system, so all server workers share sessions
+pub fn managers() -> Managers {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-managers");
+        let echo: Addr<Syn, _> = SockJSManager::<Echo>::start_default();
+        let close: Addr<Syn, _> = SockJSManager::<Close>::start_default();
+        let expiring: Addr<Syn, _> = SockJSManager::<Echo>::default()
+            .disconnect_delay(Duration::from_millis(500)).start();
+        let sharded = ShardedManager::start(4, SockJSManager::<Echo>::default);
+        let _ = tx.send((echo, close, expiring, sharded));
+        sys.run();
+    });
+    rx.recv().unwrap()
+}
+
+/// Same applications as `examples/sockjs-testsrv.rs`
+pub fn server() -> TestServer {
+    let (echo, close, expiring, sharded) = managers();
+    TestServer::with_factory(move || {
+        App::new()
+            .handler("/echo", SockJS::new(echo.clone()).maxsize(4096))
+            .handler("/close", SockJS::new(close.clone()))
+            .handler("/disabled_websocket_echo",
+                     SockJS::new(echo.clone()).disable_transports(vec!["websocket"]))
+            .handler("/cookie_needed_echo", SockJS::new(echo.clone()).cookie_needed(true))
+            .handler("/expiring", SockJS::new(expiring.clone()))
+            .handler("/sharded", SockJS::sharded(&sharded))
+    })
+}
@@ -0,0 +1,605 @@
+//! Port of sockjs-protocol test suite (`protocol/sockjs-protocol.py`),
+//! runs against in-process test server.
+extern crate actix;
+extern crate actix_web;
+extern crate futures;
+extern crate rand;
+extern crate serde_json;
+extern crate sockjs;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::http::{header, HeaderMap, Method, StatusCode};
+use actix_web::test::TestServer;
+use actix_web::*;
+use futures::Stream;
+use serde_json::Value;
+
+use sockjs::{MemoryBus, Message, SendMessage, ShardedManager, SockJS, SockJSManager};
+
+mod common;
+
+use common::{server, Echo};
+
+struct Response {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+}
+
+impl Response {
+    fn header(&self, name: header::HeaderName) -> &str {
+        self.headers.get(name).map(|h| h.to_str().unwrap()).unwrap_or("")
+    }
+}
+
+fn request(srv: &mut TestServer, method: Method, path: &str, body: &str) -> Response {
+    let req = srv.client(method, path).body(body.to_owned()).unwrap();
+    let resp = srv.execute(req.send()).unwrap();
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let body = srv.execute(resp.body()).unwrap();
+    Response{status, headers, body: String::from_utf8(body.to_vec()).unwrap()}
+}
+
+fn get(srv: &mut TestServer, path: &str) -> Response {
+    request(srv, Method::GET, path, "")
+}
+
+fn post(srv: &mut TestServer, path: &str, body: &str) -> Response {
+    request(srv, Method::POST, path, body)
+}
+
+fn session_url(prefix: &str) -> String {
+    format!("{}/000/{}", prefix, rand::random::<u32>())
+}
+
+fn ws_connect(srv: &mut TestServer, path: &str)
+              -> (ws::ClientReader, ws::ClientWriter)
+{
+    let url = srv.url(path).replace("http://", "ws://");
+    srv.execute(ws::Client::new(url).connect()).unwrap()
+}
+
+fn ws_read(srv: &mut TestServer, reader: ws::ClientReader)
+           -> (Option<ws::Message>, ws::ClientReader)
+{
+    let (item, reader) = srv.execute(reader.into_future()).map_err(|_| ()).unwrap();
+    (item, reader)
+}
+
+fn text(s: &str) -> Option<ws::Message> {
+    Some(ws::Message::Text(s.to_owned()))
+}
+
+#[test]
+fn test_greeting() {
+    let mut srv = server();
+
+    for path in &["/echo", "/echo/"] {
+        let resp = get(&mut srv, path);
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.header(header::CONTENT_TYPE), "text/plain; charset=UTF-8");
+        assert_eq!(resp.body, "Welcome to SockJS!\n");
+    }
+
+    let resp = get(&mut srv, "/echo/a/a");
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_iframe() {
+    let mut srv = server();
+
+    for path in &["/echo/iframe.html", "/echo/iframe-a.html", "/echo/iframe-0.1.2.html"] {
+        let resp = get(&mut srv, path);
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.header(header::CONTENT_TYPE), "text/html; charset=UTF-8");
+        assert!(resp.body.contains("SockJS.bootstrap_iframe()"));
+        assert!(!resp.header(header::ETAG).is_empty());
+    }
+
+    for path in &["/echo/iframe.htm", "/echo/iframe.html/a", "/echo/a/iframe.html"] {
+        assert_eq!(get(&mut srv, path).status, StatusCode::NOT_FOUND);
+    }
+
+    // cacheability
+    let etag = get(&mut srv, "/echo/iframe.html").header(header::ETAG).to_owned();
+    let req = srv.get().uri(srv.url("/echo/iframe.html"))
+        .header(header::IF_NONE_MATCH, etag).finish().unwrap();
+    let resp = srv.execute(req.send()).unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[test]
+fn test_info() {
+    let mut srv = server();
+
+    let resp = get(&mut srv, "/echo/info");
+    assert_eq!(resp.status, StatusCode::OK);
+    assert_eq!(resp.header(header::CONTENT_TYPE), "application/json;charset=UTF-8");
+    let info: Value = serde_json::from_str(&resp.body).unwrap();
+    assert_eq!(info["websocket"], Value::Bool(true));
+    assert_eq!(info["cookie_needed"], Value::Bool(false));
+    assert_eq!(info["origins"], serde_json::from_str::<Value>(r#"["*:*"]"#).unwrap());
+    assert!(info["entropy"].is_u64());
+
+    // entropy
+    let other: Value = serde_json::from_str(&get(&mut srv, "/echo/info").body).unwrap();
+    assert_ne!(info["entropy"], other["entropy"]);
+
+    let resp = request(&mut srv, Method::OPTIONS, "/echo/info", "");
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+    assert_eq!(resp.header(header::ACCESS_CONTROL_ALLOW_METHODS), "OPTIONS, GET");
+
+    let resp = get(&mut srv, "/disabled_websocket_echo/info");
+    let info: Value = serde_json::from_str(&resp.body).unwrap();
+    assert_eq!(info["websocket"], Value::Bool(false));
+
+    let resp = get(&mut srv, "/cookie_needed_echo/info");
+    let info: Value = serde_json::from_str(&resp.body).unwrap();
+    assert_eq!(info["cookie_needed"], Value::Bool(true));
+}
+
+#[test]
+fn test_session_urls() {
+    let mut srv = server();
+
+    // any value is accepted as server and session id
+    for url in &["/echo/a/a", "/echo/_/_", "/echo/1/1", "/echo/abcdefgh_i-j%20/abcdefg_i-j%20"] {
+        let resp = post(&mut srv, &format!("{}/xhr", url), "");
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.body, "o\n");
+    }
+
+    // ids containing dots or empty ids are rejected
+    for url in &["/echo/a./a", "/echo/a/a.", "/echo//", "/echo/a/"] {
+        let resp = post(&mut srv, &format!("{}/xhr", url), "");
+        assert_eq!(resp.status, StatusCode::NOT_FOUND);
+    }
+
+    // server id is ignored
+    let sid = rand::random::<u32>();
+    assert_eq!(post(&mut srv, &format!("/echo/000/{}/xhr", sid), "").body, "o\n");
+    let resp = post(&mut srv, &format!("/echo/000/{}/xhr_send", sid), r#"["a"]"#);
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+    assert_eq!(post(&mut srv, &format!("/echo/999/{}/xhr", sid), "").body, "a[\"a\"]\n");
+}
+
+#[test]
+fn test_simple_session() {
+    let mut srv = server();
+    let url = session_url("/echo");
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), r#"["a"]"#);
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+    assert_eq!(resp.body, "");
+
+    // session is shared between transports
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "a[\"a\"]\n");
+
+    // sending to unknown session
+    let resp = post(&mut srv, &format!("{}/xhr_send", session_url("/echo")), r#"["a"]"#);
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_close_session() {
+    let mut srv = server();
+    let url = session_url("/close");
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "c[3000,\"Go away!\"]\n");
+    // closed session answers with close frame until it expires
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "c[3000,\"Go away!\"]\n");
+}
+
+#[test]
+fn test_session_expiry() {
+    let mut srv = server();
+    let url = session_url("/expiring");
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+    thread::sleep(Duration::from_millis(1500));
+
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), r#"["a"]"#);
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+    // expired session id opens new session
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+}
+
+#[test]
+fn test_xhr_polling() {
+    let mut srv = server();
+    let url = session_url("/echo");
+
+    let resp = request(&mut srv, Method::OPTIONS, &format!("{}/xhr", url), "");
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+    assert_eq!(resp.header(header::ACCESS_CONTROL_ALLOW_METHODS), "OPTIONS, POST");
+
+    let resp = post(&mut srv, &format!("{}/xhr", url), "");
+    assert_eq!(resp.status, StatusCode::OK);
+    assert_eq!(resp.header(header::CONTENT_TYPE), "application/javascript; charset=UTF-8");
+    assert_eq!(resp.body, "o\n");
+
+    // invalid json
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), r#"["x"#);
+    assert_eq!(resp.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.body, "Broken JSON encoding.");
+
+    // empty payload
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), "");
+    assert_eq!(resp.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.body, "Payload expected.");
+
+    // empty frame
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), "[]");
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), r#"["a"]"#);
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "a[\"a\"]\n");
+
+    // only POST is allowed
+    assert_eq!(get(&mut srv, &format!("{}/xhr", url)).status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_xhr_streaming() {
+    let mut srv = server();
+    let url = session_url("/close");
+
+    let resp = request(&mut srv, Method::OPTIONS, &format!("{}/xhr_streaming", url), "");
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+
+    let resp = post(&mut srv, &format!("{}/xhr_streaming", url), "");
+    assert_eq!(resp.status, StatusCode::OK);
+    assert_eq!(resp.header(header::CONTENT_TYPE), "application/javascript; charset=UTF-8");
+    let prelude = format!("{}\n", "h".repeat(2048));
+    assert_eq!(resp.body, format!("{}o\nc[3000,\"Go away!\"]\n", prelude));
+}
+
+#[test]
+fn test_xhr_streaming_response_limit() {
+    let mut srv = server();
+    let url = session_url("/echo");
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+
+    // messages are buffered until streaming request is made,
+    // streaming request is finished after 4096 bytes were sent
+    let msg = format!("\"{}\"", "x".repeat(128));
+    for _ in 0..31 {
+        let resp = post(&mut srv, &format!("{}/xhr_send", url), &format!("[{}]", msg));
+        assert_eq!(resp.status, StatusCode::NO_CONTENT);
+    }
+    let resp = post(&mut srv, &format!("{}/xhr_streaming", url), "");
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.starts_with(&"h".repeat(2048)));
+    assert!(resp.body.contains(&msg));
+}
+
+#[test]
+fn test_eventsource() {
+    let mut srv = server();
+
+    let resp = get(&mut srv, &format!("{}/eventsource", session_url("/close")));
+    assert_eq!(resp.status, StatusCode::OK);
+    assert_eq!(resp.header(header::CONTENT_TYPE), "text/event-stream");
+    assert!(resp.body.starts_with("\r\ndata: o\r\n\r\n"));
+    assert!(resp.body.contains("3000"));
+    assert!(resp.body.contains("Go away!"));
+    assert!(resp.body.ends_with("\r\n\r\n"));
+}
+
+#[test]
+fn test_htmlfile() {
+    let mut srv = server();
+    let url = session_url("/close");
+
+    let resp = get(&mut srv, &format!("{}/htmlfile?c=%63allback", url));
+    assert_eq!(resp.status, StatusCode::OK);
+    assert_eq!(resp.header(header::CONTENT_TYPE), "text/html; charset=UTF-8");
+    assert!(resp.body.contains("var c = parent.callback;"));
+    assert!(resp.body.contains("<script>\np(\"o\");\n</script>\r\n"));
+    assert!(resp.body.contains("<script>\np(\"c[3000,\\\"Go away!\\\"]\");\n</script>\r\n"));
+
+    let resp = get(&mut srv, &format!("{}/htmlfile", session_url("/echo")));
+    assert_eq!(resp.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.body, "\"callback\" parameter required");
+
+    for callback in &["%20", "*", "abc(", "abc%28"] {
+        let resp = get(&mut srv, &format!("{}/htmlfile?c={}", session_url("/echo"), callback));
+        assert_eq!(resp.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(resp.body, "invalid \"callback\" parameter");
+    }
+}
+
+#[test]
+fn test_jsonp() {
+    let mut srv = server();
+    let url = session_url("/echo");
+
+    let resp = get(&mut srv, &format!("{}/jsonp?c=%63allback", url));
+    assert_eq!(resp.status, StatusCode::OK);
+    assert_eq!(resp.header(header::CONTENT_TYPE), "application/javascript; charset=UTF-8");
+    assert_eq!(resp.body, "/**/callback(\"o\");\r\n");
+
+    // form encoded payload
+    let req = srv.client(Method::POST, &format!("{}/jsonp_send", url))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body("d=%5B%22x%22%5D").unwrap();
+    let resp = srv.execute(req.send()).unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(&srv.execute(resp.body()).unwrap()[..], b"ok");
+
+    let resp = get(&mut srv, &format!("{}/jsonp?c=%63allback", url));
+    assert_eq!(resp.body, "/**/callback(\"a[\\\"x\\\"]\");\r\n");
+
+    // plain payload
+    let resp = post(&mut srv, &format!("{}/jsonp_send", url), r#"["y"]"#);
+    assert_eq!(resp.status, StatusCode::OK);
+    assert_eq!(resp.body, "ok");
+
+    let resp = get(&mut srv, &format!("{}/jsonp?c=%63allback", url));
+    assert_eq!(resp.body, "/**/callback(\"a[\\\"y\\\"]\");\r\n");
+
+    let resp = post(&mut srv, &format!("{}/jsonp_send", url), "");
+    assert_eq!(resp.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.body, "Payload expected.");
+
+    let resp = get(&mut srv, &format!("{}/jsonp", session_url("/echo")));
+    assert_eq!(resp.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.body, "\"callback\" parameter required");
+
+    let resp = get(&mut srv, &format!("{}/jsonp?c=%20", session_url("/echo")));
+    assert_eq!(resp.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.body, "invalid \"callback\" parameter");
+
+    let url = session_url("/close");
+    assert_eq!(get(&mut srv, &format!("{}/jsonp?c=x", url)).body, "/**/x(\"o\");\r\n");
+    assert_eq!(get(&mut srv, &format!("{}/jsonp?c=x", url)).body,
+               "/**/x(\"c[3000,\\\"Go away!\\\"]\");\r\n");
+}
+
+#[test]
+fn test_websocket_http_errors() {
+    let mut srv = server();
+    let url = format!("{}/websocket", session_url("/echo"));
+
+    let resp = get(&mut srv, &url);
+    assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+
+    let resp = post(&mut srv, &url, "");
+    assert_eq!(resp.status, StatusCode::METHOD_NOT_ALLOWED);
+
+    // disabled transport
+    let url = format!("{}/websocket", session_url("/disabled_websocket_echo"));
+    assert_eq!(get(&mut srv, &url).status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_websocket() {
+    let mut srv = server();
+    let (reader, mut writer) =
+        ws_connect(&mut srv, &format!("{}/websocket", session_url("/echo")));
+
+    let (item, reader) = ws_read(&mut srv, reader);
+    assert_eq!(item, text("o"));
+
+    writer.text(r#"["a"]"#);
+    let (item, reader) = ws_read(&mut srv, reader);
+    assert_eq!(item, text("a[\"a\"]"));
+
+    // empty frames are ignored
+    writer.text("[]");
+    writer.text(r#"["b"]"#);
+    let (item, reader) = ws_read(&mut srv, reader);
+    assert_eq!(item, text("a[\"b\"]"));
+
+    // broken json closes connection
+    writer.text(r#"["a"#);
+    let (item, _) = ws_read(&mut srv, reader);
+    assert_ne!(item, text("a[\"a\"]"));
+}
+
+#[test]
+fn test_websocket_close() {
+    let mut srv = server();
+    let (reader, _writer) =
+        ws_connect(&mut srv, &format!("{}/websocket", session_url("/close")));
+
+    let (item, reader) = ws_read(&mut srv, reader);
+    assert_eq!(item, text("o"));
+    let (item, _) = ws_read(&mut srv, reader);
+    assert_eq!(item, text("c[3000,\"Go away!\"]"));
+}
+
+#[test]
+fn test_raw_websocket() {
+    let mut srv = server();
+    let (reader, mut writer) = ws_connect(&mut srv, "/echo/websocket");
+
+    writer.text("Hello world!\u{fffd}");
+    let (item, _) = ws_read(&mut srv, reader);
+    assert_eq!(item, text("Hello world!\u{fffd}"));
+}
+
+#[test]
+fn test_json_encoding() {
+    let mut srv = server();
+    let url = session_url("/echo");
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+
+    // every message survives round trip through server encoder
+    let messages = ["\"quoted\" \\ back/slash", "\u{0}\u{1f}\u{7f}", "\u{2028}\u{2029}",
+                    "unicode \u{e9}\u{4e2d}\u{1f600}", "\r\n\t"];
+    let payload = serde_json::to_string(&messages).unwrap();
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), &payload);
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+
+    let resp = post(&mut srv, &format!("{}/xhr", url), "");
+    assert!(resp.body.starts_with('a'));
+    let received: Vec<String> = serde_json::from_str(resp.body[1..].trim_right()).unwrap();
+    assert_eq!(received, messages);
+}
+
+#[test]
+fn test_cookie() {
+    let mut srv = server();
+
+    for transport in &["xhr", "xhr_streaming", "eventsource", "htmlfile?c=x", "jsonp?c=x"] {
+        let url = format!("{}/{}", session_url("/close"), transport);
+        let method = if transport.starts_with("xhr") { Method::POST } else { Method::GET };
+        let resp = request(&mut srv, method, &url, "");
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.header(header::SET_COOKIE), "JSESSIONID=dummy; Path=/");
+    }
+
+    // cookie value is echoed back
+    let req = srv.client(Method::POST, &format!("{}/xhr", session_url("/cookie_needed_echo")))
+        .header(header::COOKIE, "JSESSIONID=abcdef").finish().unwrap();
+    let resp = srv.execute(req.send()).unwrap();
+    let cookie = resp.headers().get(header::SET_COOKIE).unwrap().to_str().unwrap();
+    assert_eq!(cookie, "JSESSIONID=abcdef; Path=/");
+}
+
+#[test]
+fn test_sharded_manager() {
+    let mut srv = server();
+
+    // sessions land on different shards
+    let urls: Vec<_> = (0..16).map(|_| session_url("/sharded")).collect();
+    for url in &urls {
+        assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+    }
+    for (i, url) in urls.iter().enumerate() {
+        let resp = post(&mut srv, &format!("{}/xhr_send", url), &format!("[\"{}\"]", i));
+        assert_eq!(resp.status, StatusCode::NO_CONTENT);
+    }
+    for (i, url) in urls.iter().enumerate() {
+        assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, format!("a[\"{}\"]\n", i));
+    }
+
+    let resp = post(&mut srv, &format!("{}/xhr_send", session_url("/sharded")), r#"["a"]"#);
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+#[should_panic(expected = "Shard managers can not be connected to a bus")]
+fn test_sharded_manager_rejects_bus() {
+    let _sys = System::new("test");
+    ShardedManager::start(2, || SockJSManager::<Echo>::default().bus(MemoryBus::new()));
+}
+
+/// Two managers connected with in-process bus
+fn bus_server() -> (TestServer, Addr<Syn, SockJSManager<Echo>>) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-bus");
+        let bus = MemoryBus::new();
+        let a: Addr<Syn, _> = SockJSManager::<Echo>::default().bus(bus.clone()).start();
+        let b: Addr<Syn, _> = SockJSManager::<Echo>::default().bus(bus).start();
+        let _ = tx.send((a, b));
+        sys.run();
+    });
+    let (a, b) = rx.recv().unwrap();
+    let manager = a.clone();
+    let srv = TestServer::with_factory(move || {
+        App::new()
+            .handler("/a", SockJS::new(a.clone()))
+            .handler("/b", SockJS::new(b.clone()))
+    });
+    (srv, manager)
+}
+
+#[test]
+fn test_memory_bus() {
+    let (mut srv, a) = bus_server();
+
+    let url = session_url("/b");
+    let sid = url.rsplit('/').next().unwrap().to_owned();
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+
+    // session is owned by the other manager
+    a.do_send(SendMessage{sid, msg: Message("from a".to_owned())});
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "a[\"from a\"]\n");
+
+    // sessions of both managers are reachable
+    let url = session_url("/a");
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), r#"["echo"]"#);
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "a[\"echo\"]\n");
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_metrics_per_application() {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-metrics");
+        let a: Addr<Syn, _> = SockJSManager::<Echo>::start_default();
+        let b: Addr<Syn, _> = SockJSManager::<Echo>::start_default();
+        let _ = tx.send((a, b));
+        sys.run();
+    });
+    let (a, b) = rx.recv().unwrap();
+    let mut srv = TestServer::with_factory(move || {
+        App::new()
+            .handler("/a", SockJS::new(a.clone()).metrics(true))
+            .handler("/b", SockJS::new(b.clone()).metrics(true))
+    });
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", session_url("/a")), "").body, "o\n");
+
+    // applications do not report sessions of each other
+    let opened = |body: &str| body.lines()
+        .find(|l| l.starts_with("sockjs_sessions_opened_total "))
+        .map(|l| l.to_owned());
+    let resp = get(&mut srv, "/a/metrics");
+    assert_eq!(resp.status, StatusCode::OK);
+    assert_eq!(opened(&resp.body), Some("sockjs_sessions_opened_total 1".to_owned()));
+    let resp = get(&mut srv, "/b/metrics");
+    assert_eq!(opened(&resp.body), Some("sockjs_sessions_opened_total 0".to_owned()));
+}
+
+#[cfg(feature = "stomp")]
+#[test]
+fn test_stomp_duplicate_subscription_id() {
+    use sockjs::StompSession;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-stomp");
+        let sm: Addr<Syn, _> = SockJSManager::<StompSession>::start_default();
+        let _ = tx.send(sm);
+        sys.run();
+    });
+    let sm = rx.recv().unwrap();
+    let mut srv = TestServer::with_factory(
+        move || App::new().handler("/stomp", SockJS::new(sm.clone())));
+    let url = session_url("/stomp");
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "o\n");
+    let frames = serde_json::to_string(&[
+        "CONNECT\naccept-version:1.2\n\n\0",
+        "SUBSCRIBE\nid:0\ndestination:/a\n\n\0",
+        "SUBSCRIBE\nid:0\ndestination:/b\n\n\0",
+    ]).unwrap();
+    let resp = post(&mut srv, &format!("{}/xhr_send", url), &frames);
+    assert_eq!(resp.status, StatusCode::NO_CONTENT);
+
+    // second subscription does not replace the first one
+    let body = post(&mut srv, &format!("{}/xhr", url), "").body;
+    let msgs: Vec<String> = serde_json::from_str(body[1..].trim_right()).unwrap();
+    assert_eq!(msgs.len(), 2);
+    assert!(msgs[0].starts_with("CONNECTED\n"));
+    assert!(msgs[1].starts_with("ERROR\n"));
+    assert!(msgs[1].contains("message:Subscription id is already in use\n"));
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), "").body, "c[3000,\"Go away!\"]\n");
+}
@@ -0,0 +1,495 @@
+//! Ordering of session lifecycle callbacks and peer messages
+extern crate actix;
+extern crate actix_web;
+extern crate futures;
+extern crate rand;
+extern crate serde_json;
+extern crate sockjs;
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::http::Method;
+use actix_web::test::TestServer;
+use actix_web::*;
+use futures::Stream;
+
+use sockjs::{CloseReason, CreateSession, Disconnect, DisconnectWhere, Message, Placement, Rpc,
+             SendMessage, Session, SessionError, SessionEvent, SockJS, SockJSContext,
+             SockJSManager};
+
+/// Callbacks recorded by all sessions, in order, and transport
+/// releases reported by the manager
+#[derive(Clone)]
+struct Events {
+    log: Arc<Mutex<Vec<(String, String)>>>,
+    releases: Arc<Mutex<Vec<String>>>,
+    tx: Arc<Mutex<mpsc::Sender<()>>>,
+    rx: Arc<Mutex<mpsc::Receiver<()>>>,
+}
+
+impl Default for Events {
+    fn default() -> Events {
+        let (tx, rx) = mpsc::channel();
+        Events {
+            log: Arc::new(Mutex::new(Vec::new())),
+            releases: Arc::new(Mutex::new(Vec::new())),
+            tx: Arc::new(Mutex::new(tx)),
+            rx: Arc::new(Mutex::new(rx)),
+        }
+    }
+}
+
+impl Events {
+    fn record(&self, sid: &str, ev: String) {
+        self.log.lock().unwrap().push((sid.to_owned(), ev));
+        let _ = self.tx.lock().unwrap().send(());
+    }
+
+    fn record_release(&self, sid: String) {
+        self.releases.lock().unwrap().push(sid);
+        let _ = self.tx.lock().unwrap().send(());
+    }
+
+    fn all(&self) -> Vec<String> {
+        self.log.lock().unwrap().iter().map(|&(_, ref ev)| ev.clone()).collect()
+    }
+
+    /// Events of single session
+    fn session(&self, sid: &str) -> Vec<String> {
+        self.log.lock().unwrap().iter()
+            .filter(|&&(ref s, _)| s == sid)
+            .map(|&(_, ref ev)| ev.clone())
+            .collect()
+    }
+
+    /// Number of times transports released session
+    fn releases(&self, sid: &str) -> usize {
+        self.releases.lock().unwrap().iter().filter(|s| *s == sid).count()
+    }
+
+    /// Wait until recorded events satisfy `done`
+    fn wait<F: Fn(&Events) -> bool>(&self, done: F) {
+        let rx = self.rx.lock().unwrap();
+        while !done(self) {
+            rx.recv_timeout(Duration::from_secs(5)).expect("event is not observed");
+        }
+    }
+}
+
+/// Manager observer that records transport releases
+struct Observer(Events);
+
+impl Actor for Observer {
+    type Context = Context<Self>;
+}
+
+impl Handler<SessionEvent> for Observer {
+    type Result = ();
+
+    fn handle(&mut self, ev: SessionEvent, _: &mut Context<Self>) {
+        if let SessionEvent::TransportReleased{sid} = ev {
+            self.0.record_release(sid);
+        }
+    }
+}
+
+/// Echo session that records callbacks
+#[derive(Default)]
+struct Recorder {
+    events: Events,
+}
+
+impl Recorder {
+    fn record<T: Into<String>>(&self, ctx: &SockJSContext<Self>, ev: T) {
+        self.events.record(ctx.sid(), ev.into());
+    }
+}
+
+impl Actor for Recorder {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Recorder {
+    fn opened(&mut self, ctx: &mut SockJSContext<Self>) {
+        self.record(ctx, "opened");
+    }
+    fn acquired(&mut self, ctx: &mut SockJSContext<Self>) {
+        self.record(ctx, "acquired");
+    }
+    fn released(&mut self, ctx: &mut SockJSContext<Self>) {
+        self.record(ctx, "released");
+    }
+    fn closed(&mut self, ctx: &mut SockJSContext<Self>, reason: CloseReason) {
+        self.record(ctx, format!("closed {:?}", reason));
+    }
+}
+
+impl Handler<Message> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        self.record(ctx, format!("message {}", msg.0));
+        if msg.0.starts_with('{') {
+            // responses to rpc calls
+            let _ = Rpc::new().handle(self, msg, ctx);
+        } else if msg.0 == "call" {
+            let call = ctx.call("ping", (), Duration::from_millis(300));
+            ctx.spawn(call.into_actor(self).then(|res, act, ctx| {
+                let result = match res {
+                    Ok(result) => result.to_string(),
+                    Err(err) => err.to_string(),
+                };
+                act.record(ctx, format!("call {}", result));
+                ctx.send(result);
+                actix::fut::ok(())
+            }));
+        } else if msg.0.starts_with("all ") {
+            ctx.broadcast(&msg.0[4..]);
+        } else if msg.0.starts_with("close ") {
+            if ctx.close_with(msg.0[6..].parse().unwrap(), "Bye").is_err() {
+                ctx.send("invalid code");
+            }
+        } else {
+            ctx.send(msg);
+        }
+    }
+}
+
+fn server(events: Events, arbiters: Option<Placement>)
+          -> (TestServer, Addr<Syn, SockJSManager<Recorder>>)
+{
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-managers");
+        let observer: Addr<Syn, _> = Observer(events.clone()).start();
+        let mut sm = SockJSManager::new(move || Recorder{events: events.clone()})
+            .disconnect_delay(Duration::from_millis(300))
+            .subscribe(observer.recipient());
+        if let Some(placement) = arbiters {
+            sm = sm.arbiters(2, placement);
+        }
+        let sm: Addr<Syn, _> = sm.start();
+        let _ = tx.send(sm);
+        sys.run();
+    });
+    let sm: Addr<Syn, _> = rx.recv().unwrap();
+
+    let addr = sm.clone();
+    let srv = TestServer::with_factory(
+        move || App::new().handler("/recorder", SockJS::new(sm.clone())));
+    (srv, addr)
+}
+
+fn session_url() -> String {
+    format!("/recorder/000/{}", rand::random::<u32>())
+}
+
+fn post(srv: &mut TestServer, path: &str, body: &str) -> String {
+    let req = srv.client(Method::POST, path).body(body.to_owned()).unwrap();
+    let resp = srv.execute(req.send()).unwrap();
+    let body = srv.execute(resp.body()).unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/// Messages of `a[...]` frame
+fn frame_messages(body: &str) -> Vec<String> {
+    assert!(body.starts_with('a'), "not a message frame: {}", body);
+    serde_json::from_str(body[1..].trim_right()).unwrap()
+}
+
+/// Wait until session gets closed, returns recorded events
+fn closed(events: &Events) -> Vec<String> {
+    events.wait(|events| events.all().iter().any(|ev| ev.starts_with("closed")));
+    events.all()
+}
+
+/// Wait until transports released session `count` times
+fn released(events: &Events, sid: &str, count: usize) {
+    events.wait(|events| events.releases(sid) >= count);
+}
+
+fn messages(events: &[String]) -> Vec<&str> {
+    events.iter().filter(|ev| ev.starts_with("message")).map(|ev| ev.as_str()).collect()
+}
+
+fn ws_connect(srv: &mut TestServer, path: &str) -> (ws::ClientReader, ws::ClientWriter) {
+    let url = srv.url(&format!("{}/websocket", path)).replace("http://", "ws://");
+    srv.execute(ws::Client::new(url).connect()).unwrap()
+}
+
+fn ws_read(srv: &mut TestServer, reader: ws::ClientReader) -> (Option<String>, ws::ClientReader) {
+    let (msg, reader) = srv.execute(reader.into_future()).map_err(|_| ()).unwrap();
+    match msg {
+        Some(ws::Message::Text(text)) => (Some(text), reader),
+        _ => (None, reader),
+    }
+}
+
+fn websocket_ordering(arbiters: Option<Placement>) {
+    let events = Events::default();
+    let (mut srv, _) = server(events.clone(), arbiters);
+
+    let (reader, mut writer) = ws_connect(&mut srv, &session_url());
+    let (msg, mut reader) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("o".to_owned()));
+
+    for i in 0..10 {
+        writer.text(format!("[\"m{}\"]", i));
+    }
+    for i in 0..10 {
+        let (msg, r) = ws_read(&mut srv, reader);
+        assert_eq!(msg, Some(format!("a[\"m{}\"]", i)));
+        reader = r;
+    }
+    writer.close(None);
+
+    let events = closed(&events);
+    assert_eq!(&events[..2], &["opened", "acquired"]);
+    assert_eq!(messages(&events[2..]),
+               (0..10).map(|i| format!("message m{}", i)).collect::<Vec<_>>());
+    assert_eq!(events.last().unwrap(), "closed Peer { code: None, reason: None }");
+    assert_eq!(events.iter().filter(|ev| ev.starts_with("closed")).count(), 1);
+}
+
+fn xhr_ordering(arbiters: Option<Placement>) {
+    let events = Events::default();
+    let (mut srv, _) = server(events.clone(), arbiters);
+    let url = session_url();
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "o\n");
+    assert_eq!(post(&mut srv, &format!("{}/xhr_send", url), r#"["a","b","c"]"#), "");
+    // echoes get buffered while session is not acquired,
+    // transport acquires session after messages are handled
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "a[\"a\",\"b\",\"c\"]\n");
+
+    // session expires after disconnect delay
+    let events = closed(&events);
+    assert_eq!(&events[..2], &["opened", "acquired"]);
+    assert_eq!(messages(&events), vec!["message a", "message b", "message c"]);
+    assert_eq!(events.last().unwrap(), "closed Expired");
+}
+
+#[test]
+fn test_websocket_ordering() {
+    websocket_ordering(None);
+}
+
+#[test]
+fn test_xhr_ordering() {
+    xhr_ordering(None);
+}
+
+#[test]
+fn test_arbiter_pool_ordering() {
+    websocket_ordering(Some(Placement::RoundRobin));
+    xhr_ordering(Some(Placement::Hash));
+}
+
+#[test]
+fn test_create_session() {
+    let events = Events::default();
+    let (mut srv, sm) = server(events.clone(), None);
+
+    let sid = srv.execute(sm.send(CreateSession{sid: Some("handoff".to_owned())})).unwrap();
+    assert_eq!(sid, Ok("handoff".to_owned()));
+    let res = srv.execute(sm.send(CreateSession{sid: Some("handoff".to_owned())})).unwrap();
+    assert_eq!(res, Err(SessionError::Acquired));
+
+    // generated session id
+    let generated = srv.execute(sm.send(CreateSession::default())).unwrap().unwrap();
+    assert_eq!(generated.len(), 16);
+
+    // buffered until peer connects
+    sm.do_send(SendMessage{sid: "handoff".to_owned(), msg: Message("pushed".to_owned())});
+
+    let (reader, _writer) = ws_connect(&mut srv, "/recorder/000/handoff");
+    let (msg, reader) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("o".to_owned()));
+    let (msg, _) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("a[\"pushed\"]".to_owned()));
+
+    assert_eq!(events.session("handoff"), vec!["opened", "acquired"]);
+    assert_eq!(events.session(&generated), vec!["opened"]);
+}
+
+#[test]
+fn test_disconnect() {
+    let events = Events::default();
+    let (mut srv, sm) = server(events.clone(), None);
+
+    let (reader, mut writer) = ws_connect(&mut srv, "/recorder/000/kicked");
+    let (msg, reader) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("o".to_owned()));
+
+    let disconnect = || Disconnect{sid: "kicked".to_owned(), code: 4001, reason: "Banned".to_owned()};
+    assert_eq!(srv.execute(sm.send(disconnect())).unwrap(), Ok(()));
+    let (msg, _) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("c[4001,\"Banned\"]".to_owned()));
+    writer.close(None);
+
+    let events = closed(&events);
+    assert_eq!(events.last().unwrap(), "closed Kicked(Custom(4001, \"Banned\"))");
+    // session is closed already
+    assert_eq!(srv.execute(sm.send(disconnect())).unwrap(), Err(()));
+
+    // later requests get the same close frame
+    released(&events, "kicked", 1);
+    assert_eq!(post(&mut srv, "/recorder/000/kicked/xhr", ""), "c[4001,\"Banned\"]\n");
+
+    let res = srv.execute(sm.send(
+        Disconnect{sid: "unknown".to_owned(), code: 4001, reason: "Banned".to_owned()})).unwrap();
+    assert_eq!(res, Err(()));
+}
+
+#[test]
+fn test_close_with() {
+    let events = Events::default();
+    let (mut srv, _) = server(events.clone(), None);
+    let url = session_url();
+    let sid = url.rsplit('/').next().unwrap().to_owned();
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "o\n");
+    post(&mut srv, &format!("{}/xhr_send", url), r#"["close 1000"]"#);
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "a[\"invalid code\"]\n");
+
+    post(&mut srv, &format!("{}/xhr_send", url), r#"["close 4000"]"#);
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "c[4000,\"Bye\"]\n");
+
+    // later requests get the same close frame
+    released(&events, &sid, 3);
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "c[4000,\"Bye\"]\n");
+}
+
+#[test]
+fn test_rpc_call() {
+    let events = Events::default();
+    let (mut srv, _) = server(events.clone(), None);
+    let url = session_url();
+    let (xhr, xhr_send) = (format!("{}/xhr", url), format!("{}/xhr_send", url));
+
+    assert_eq!(post(&mut srv, &xhr, ""), "o\n");
+    post(&mut srv, &xhr_send, r#"["call"]"#);
+    // null params are omitted
+    assert_eq!(frame_messages(&post(&mut srv, &xhr, "")),
+               vec![r#"{"id":1,"jsonrpc":"2.0","method":"ping"}"#]);
+
+    let resp = serde_json::to_string(&[r#"{"jsonrpc":"2.0","result":"pong","id":1}"#]).unwrap();
+    post(&mut srv, &xhr_send, &resp);
+    assert_eq!(frame_messages(&post(&mut srv, &xhr, "")), vec!["\"pong\""]);
+
+    post(&mut srv, &xhr_send, r#"["echo"]"#);
+    assert_eq!(post(&mut srv, &xhr, ""), "a[\"echo\"]\n");
+
+    // unanswered call times out
+    post(&mut srv, &xhr_send, r#"["call"]"#);
+    assert_eq!(frame_messages(&post(&mut srv, &xhr, "")),
+               vec![r#"{"id":2,"jsonrpc":"2.0","method":"ping"}"#]);
+    assert_eq!(frame_messages(&post(&mut srv, &xhr, "")), vec!["Request timed out (-32000)"]);
+
+    // timeout of answered call is cancelled, it would fire before the second one
+    let results: Vec<_> = events.all().into_iter().filter(|ev| ev.starts_with("call")).collect();
+    assert_eq!(results, vec!["call \"pong\"", "call Request timed out (-32000)"]);
+}
+
+#[test]
+fn test_disconnect_where() {
+    let events = Events::default();
+    let (mut srv, sm) = server(events.clone(), None);
+
+    for sid in &["bulk-1", "bulk-2", "other"] {
+        let res = srv.execute(sm.send(CreateSession{sid: Some(sid.to_string())})).unwrap();
+        assert!(res.is_ok());
+    }
+
+    let kick = DisconnectWhere::new(|s| s.sid.starts_with("bulk"), 4002, "Maintenance");
+    assert_eq!(srv.execute(sm.send(kick.clone())).unwrap(), Ok(2));
+    assert_eq!(srv.execute(sm.send(kick)).unwrap(), Ok(0));
+
+    assert_eq!(post(&mut srv, "/recorder/000/bulk-1/xhr", ""), "c[4002,\"Maintenance\"]\n");
+    assert_eq!(post(&mut srv, "/recorder/000/other/xhr", ""), "o\n");
+
+    // untracked sessions do not belong to any user
+    let kick = DisconnectWhere::user("alice", 4002, "Maintenance");
+    assert_eq!(srv.execute(sm.send(kick)).unwrap(), Ok(0));
+}
+
+#[test]
+fn test_outbound_ordering() {
+    let events = Events::default();
+    let (mut srv, sm) = server(events.clone(), None);
+    let url = session_url();
+    let sid = url.rsplit('/').next().unwrap().to_owned();
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "o\n");
+
+    // messages sent to the session and session's own messages share one queue,
+    // including frames buffered while transport is not connected
+    for round in 0..2 {
+        for i in 0..3 {
+            let msg = Message(format!("pushed {}-{}", round, i));
+            srv.execute(sm.send(SendMessage{sid: sid.clone(), msg})).unwrap();
+            let body = format!("[\"echo {}-{}\"]", round, i);
+            assert_eq!(post(&mut srv, &format!("{}/xhr_send", url), &body), "");
+        }
+
+        let expected: Vec<_> = (0..3)
+            .flat_map(|i| vec![format!("\"pushed {}-{}\"", round, i),
+                               format!("\"echo {}-{}\"", round, i)])
+            .collect();
+        assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""),
+                   format!("a[{}]\n", expected.join(",")));
+    }
+}
+
+#[test]
+fn test_broadcast_ordering() {
+    let events = Events::default();
+    let (mut srv, sm) = server(events.clone(), None);
+    let url = session_url();
+    let sid = url.rsplit('/').next().unwrap().to_owned();
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "o\n");
+    let (reader, mut writer) = ws_connect(&mut srv, &session_url());
+    let (msg, reader) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("o".to_owned()));
+
+    // broadcast is queued between messages sent to the session
+    let push = |i| SendMessage{sid: sid.clone(), msg: Message(format!("pushed {}", i))};
+    srv.execute(sm.send(push(0))).unwrap();
+    writer.text("[\"all hello\"]");
+    let (msg, _) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("a[\"hello\"]".to_owned()));
+    srv.execute(sm.send(push(1))).unwrap();
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""),
+               "a[\"pushed 0\",\"hello\",\"pushed 1\"]\n");
+}
+
+#[test]
+fn test_websocket_reconnect() {
+    let events = Events::default();
+    let (mut srv, sm) = server(events.clone(), None);
+    let url = session_url();
+    let sid = url.rsplit('/').next().unwrap().to_owned();
+
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), ""), "o\n");
+    assert_eq!(post(&mut srv, &format!("{}/xhr_send", url), r#"["echo"]"#), "");
+    srv.execute(sm.send(SendMessage{sid: sid.clone(), msg: Message("pushed".to_owned())}))
+        .unwrap();
+
+    // frames buffered while peer was away are sent to the new transport first
+    let (reader, mut writer) = ws_connect(&mut srv, &url);
+    let (msg, reader) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("a[\"echo\",\"pushed\"]".to_owned()));
+
+    writer.text("[\"again\"]");
+    let (msg, _) = ws_read(&mut srv, reader);
+    assert_eq!(msg, Some("a[\"again\"]".to_owned()));
+
+    let events = events.all();
+    assert_eq!(&events[..4], &["opened", "acquired", "released", "message echo"]);
+    assert!(events[4..].iter().any(|ev| ev == "acquired"));
+}